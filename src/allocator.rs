@@ -1,5 +1,7 @@
-use std::ptr::NonNull;
-use std::mem::size_of;
+use core::error::Error;
+use core::fmt;
+use core::ptr::NonNull;
+use core::mem::size_of;
 
 use crate::constants;
 use crate::raw_ptr::RawPtr;
@@ -8,8 +10,21 @@ use crate::raw_ptr::RawPtr;
 pub enum AllocError {
     BadRequest,
     OOM,
+    DoubleFree,
 }
 
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocError::BadRequest => write!(f, "invalid allocation request"),
+            AllocError::OOM => write!(f, "out of memory"),
+            AllocError::DoubleFree => write!(f, "object freed twice"),
+        }
+    }
+}
+
+impl Error for AllocError {}
+
 pub trait AllocTypeId: Copy + Clone {}
 
 pub trait AllocObject<T: AllocTypeId> {
@@ -21,16 +36,45 @@ pub trait AllocHeader: Sized {
 
     fn new<O: AllocObject<Self::TypeId>>(size: u32, size_class: SizeClass, mark: Mark) -> Self;
     fn new_array(size: ArraySize, size_class: SizeClass, mark: Mark) -> Self;
+    fn new_bytes(type_id: Self::TypeId, size: ArraySize, size_class: SizeClass, mark: Mark) -> Self;
+    // `new`, sized from `O` itself. anything with a trailer still needs `new`.
+    fn new_typed<O: AllocObject<Self::TypeId>>(size_class: SizeClass, mark: Mark) -> Self {
+        Self::new::<O>(size_of::<O>() as ArraySize, size_class, mark)
+    }
     fn mark(&mut self);
+    fn unmark(&mut self);
+    // only dealloc frees, so the header can tell a second dealloc apart
+    fn mark_freed(&mut self);
+    // evacuation leaves the old copy's payload holding the new address
+    fn mark_forwarded(&mut self);
+    // back to Allocated, leaving the rest of the header as it was
+    fn reset_mark(&mut self);
     fn is_marked(&self) -> bool;
+    fn mark_state(&self) -> Mark;
+    fn pin(&mut self);
+    fn unpin(&mut self);
+    fn is_pinned(&self) -> bool;
     fn size_class(&self) -> SizeClass;
     fn size(&self) -> u32;
     fn type_id(&self) -> Self::TypeId;
 }
 
+pub trait Trace {
+    fn trace(&self, mark: &mut dyn FnMut(NonNull<()>));
+}
+
+// for objects whose managed pointers sit at fixed byte offsets, so marking
+// can read them without a call. each offset holds a `*const ()`, null for
+// no edge.
+pub trait PointerLayout {
+    const PTR_OFFSETS: &'static [usize];
+}
+
 pub trait AllocRaw {
     type Header: AllocHeader;
 
+    /// Zero-sized objects take no space and get no header, so the returned
+    /// pointer is dangling and must not be passed to `get_header`.
     fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
     where
         T: AllocObject<<Self::Header as AllocHeader>::TypeId>;
@@ -56,6 +100,19 @@ impl SizeClass {
             _ => Err(AllocError::BadRequest),
         }
     }
+
+    // `alloc_size` includes the padded header. aligning it can cost up to
+    // `align - ALLOC_ALIGN` more bytes, and anything that no longer fits an
+    // empty block has to be large.
+    pub fn get_for_aligned_size(alloc_size: usize, align: usize) -> Result<SizeClass, AllocError> {
+        let padded_size = alloc_size.saturating_add(align.saturating_sub(constants::ALLOC_ALIGN));
+
+        match SizeClass::get_for_size(alloc_size)? {
+            SizeClass::Large => Ok(SizeClass::Large),
+            _ if padded_size > constants::BLOCK_CAPACITY => Ok(SizeClass::Large),
+            size_class => Ok(size_class),
+        }
+    }
 }
 
 pub type ArraySize = u32;
@@ -66,12 +123,30 @@ pub enum Mark {
     Allocated,
     Unmarked,
     Marked,
+    Freed,
+    Forwarded,
 }
 
 pub fn add_alignment_padding(object_size: usize) -> usize {
     let align = size_of::<usize>();
 
-    if (object_size % align) == 0 { return object_size; }
+    if object_size.is_multiple_of(align) { return object_size; }
 
     object_size + (align - (object_size % align))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_error_display() {
+        assert!(AllocError::BadRequest.to_string() == "invalid allocation request");
+        assert!(AllocError::OOM.to_string() == "out of memory");
+        assert!(AllocError::DoubleFree.to_string() == "object freed twice");
+
+        let boxed: Box<dyn Error> = Box::new(AllocError::OOM);
+
+        assert!(boxed.to_string() == "out of memory");
+    }
+}
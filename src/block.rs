@@ -1,5 +1,10 @@
-use std::alloc::{Layout, alloc, dealloc};
-use std::ptr::NonNull;
+use alloc::alloc::{Layout, alloc, dealloc};
+use core::error::Error;
+use core::fmt;
+use core::ptr::NonNull;
+use alloc::sync::Arc;
+
+use crate::constants;
 
 pub type BlockPtr = NonNull<u8>;
 pub type BlockSize = usize;
@@ -8,22 +13,61 @@ pub type BlockSize = usize;
 pub enum BlockError {
     BadRequest,
     OOM,
+    Misaligned,
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::BadRequest => write!(f, "invalid block size"),
+            BlockError::OOM => write!(f, "out of memory"),
+            BlockError::Misaligned => write!(f, "block source returned misaligned memory"),
+        }
+    }
+}
+
+impl Error for BlockError {}
+
+// where block memory comes from when it isn't the global allocator. blocks
+// ask for alignment equal to their size, and memory that isn't is handed
+// straight back.
+pub trait BlockSource: Send + Sync {
+    fn alloc(&self, size: BlockSize, align: usize) -> Option<BlockPtr>;
+
+    /// # Safety
+    ///
+    /// `ptr` must have come from `alloc` on this source with the same size
+    /// and alignment, and must not be used afterwards.
+    unsafe fn dealloc(&self, ptr: BlockPtr, size: BlockSize, align: usize);
 }
 
 pub struct Block {
     ptr: BlockPtr,
     size: BlockSize,
+    source: Option<Arc<dyn BlockSource>>,
 }
 
+// a block owns its allocation outright, so it can move between threads
+unsafe impl Send for Block {}
+
 impl Block {
+    #[cfg(any(feature = "std", test))]
     pub fn new(size: BlockSize) -> Result<Block, BlockError> {
+        Block::new_in(size, None)
+    }
+
+    // `None` takes the memory from the global allocator
+    pub fn new_in(size: BlockSize, source: Option<Arc<dyn BlockSource>>) -> Result<Block, BlockError> {
         let layout = Layout::from_size_align(size, size);
 
         if layout.is_err() {
             return Err(BlockError::BadRequest);
         }
 
-        let unchecked_ptr = unsafe { alloc(layout.unwrap()) };
+        let unchecked_ptr = match source {
+            Some(ref source) => source.alloc(size, size).map_or(core::ptr::null_mut(), NonNull::as_ptr),
+            None => unsafe { alloc(layout.unwrap()) },
+        };
 
         if unchecked_ptr.is_null() {
             return Err(BlockError::OOM);
@@ -31,32 +75,47 @@ impl Block {
 
         let ptr = unsafe { NonNull::new_unchecked(unchecked_ptr) };
 
-        Ok(Block { ptr, size })
+        // masking for the block base depends on it
+        if !(unchecked_ptr as usize).is_multiple_of(size) {
+            if let Some(ref source) = source {
+                unsafe { source.dealloc(ptr, size, size) };
+            }
+
+            return Err(BlockError::Misaligned);
+        }
+
+        Ok(Block { ptr, size, source })
     }
 
-    pub fn into_mut_ptr(self) -> BlockPtr {
-        self.ptr
+    pub fn new_rounded_in(min_size: BlockSize, source: Option<Arc<dyn BlockSource>>) -> Result<Block, BlockError> {
+        match min_size.checked_next_power_of_two() {
+            Some(size) => Block::new_in(size, source),
+            None => Err(BlockError::BadRequest),
+        }
     }
 
     pub fn size(&self) -> BlockSize {
         self.size
     }
 
-    pub unsafe fn from_raw_parts(ptr: BlockPtr, size: BlockSize) -> Block {
-        Block { ptr, size }
-    }
-
     pub fn as_ptr(&self) -> *const u8 {
         self.ptr.as_ptr()
     }
+
+    // blocks are aligned to their size, so masking any pointer into a
+    // BLOCK_SIZE block gives back its base
+    pub fn base_of(ptr: *const u8) -> *const u8 {
+        (ptr as usize & !(constants::BLOCK_SIZE - 1)) as *const u8
+    }
 }
 
 impl Drop for Block {
     fn drop(&mut self) {
         unsafe {
-            let layout = Layout::from_size_align_unchecked(self.size, self.size);
-
-            dealloc(self.ptr.as_ptr(), layout);
+            match self.source {
+                Some(ref source) => source.dealloc(self.ptr, self.size, self.size),
+                None => dealloc(self.ptr.as_ptr(), Layout::from_size_align_unchecked(self.size, self.size)),
+            }
         }
     }
 }
@@ -89,4 +148,73 @@ mod tests {
             assert!(result.size == size.pow(i));
         }
     }
+
+    #[test]
+    fn new_rounded_block() {
+        let small = Block::new_rounded_in(3, None).unwrap();
+        let large = Block::new_rounded_in(9000, None).unwrap();
+
+        assert!(small.size() == 4);
+        assert!(large.size() == 16384);
+        assert!((large.as_ptr() as usize).is_multiple_of(16384));
+    }
+
+    #[test]
+    fn new_rounded_keeps_power_of_2() {
+        let result = Block::new_rounded_in(4096, None).unwrap();
+
+        assert!(result.size() == 4096);
+    }
+
+    #[test]
+    fn new_rounded_bad_request() {
+        let result = Block::new_rounded_in(usize::MAX, None);
+
+        assert!(result.err().unwrap() == BlockError::BadRequest);
+    }
+
+    #[test]
+    fn base_of_interior_ptr() {
+        let blocks: Vec<Block> = (0..4).map(|_| Block::new(constants::BLOCK_SIZE).unwrap()).collect();
+
+        for block in &blocks {
+            let interior = unsafe { block.as_ptr().add(constants::BLOCK_SIZE / 2 + 3) };
+            let last = unsafe { block.as_ptr().add(constants::BLOCK_SIZE - 1) };
+
+            assert!(Block::base_of(block.as_ptr()) == block.as_ptr());
+            assert!(Block::base_of(interior) == block.as_ptr());
+            assert!(Block::base_of(last) == block.as_ptr());
+        }
+    }
+
+    struct MisalignedSource;
+
+    impl BlockSource for MisalignedSource {
+        fn alloc(&self, size: BlockSize, _align: usize) -> Option<BlockPtr> {
+            let ptr = unsafe { alloc(Layout::from_size_align(2 * size, size).unwrap()) };
+
+            NonNull::new(ptr).map(|ptr| unsafe { ptr.add(8) })
+        }
+
+        unsafe fn dealloc(&self, ptr: BlockPtr, size: BlockSize, _align: usize) {
+            dealloc(ptr.as_ptr().sub(8), Layout::from_size_align(2 * size, size).unwrap());
+        }
+    }
+
+    #[test]
+    fn misaligned_source() {
+        let result = Block::new_in(1024, Some(Arc::new(MisalignedSource)));
+
+        assert!(result.err().unwrap() == BlockError::Misaligned);
+    }
+
+    #[test]
+    fn block_error_display() {
+        assert!(BlockError::BadRequest.to_string() == "invalid block size");
+        assert!(BlockError::OOM.to_string() == "out of memory");
+
+        let boxed: Box<dyn Error> = BlockError::BadRequest.into();
+
+        assert!(boxed.to_string() == "invalid block size");
+    }
 }
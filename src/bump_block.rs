@@ -1,136 +1,667 @@
-use crate::block::{BlockError, Block};
+use crate::block::{BlockError, Block, BlockSource};
 use crate::allocator::AllocError;
 use crate::constants;
 
-use std::ptr::write;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::{take, MaybeUninit};
+use core::cmp::{max, min};
+use core::ptr::{copy, read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU8, Ordering};
 
 impl From<BlockError> for AllocError {
     fn from(error: BlockError) -> AllocError {
         match error {
             BlockError::BadRequest => AllocError::BadRequest,
-            BlockError::OOM => AllocError::OOM,
+            // the source couldn't give usable memory
+            BlockError::OOM | BlockError::Misaligned => AllocError::OOM,
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HoleStrategy {
+    FirstFit,
+    BestFit,
+}
+
+// a block's line and block marks, settable from another thread while the
+// block keeps allocating. the block reads and writes its marks atomically
+// too, so the two never race.
+#[derive(Clone, Copy)]
+pub struct AtomicMarks {
+    base: *const u8,
+    meta: *const AtomicU8,
+    line_size: usize,
+    line_count: usize,
+    mark_generation: u8,
+}
+
+// the marks are only ever touched atomically. whoever hands the view out
+// keeps the block alive while it's used.
+unsafe impl Send for AtomicMarks {}
+unsafe impl Sync for AtomicMarks {}
+
+impl AtomicMarks {
+    fn mark(&self, index: usize) -> &AtomicU8 {
+        unsafe { &*self.meta.add(index) }
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        Block::base_of(ptr) == self.base && (ptr as usize - self.base as usize) < constants::BLOCK_CAPACITY
+    }
+
+    // `ptr` must be in the block. conservative spans can run off its end.
+    pub fn mark_span_atomic(&self, ptr: *const u8, size: usize) {
+        let offset = ptr as usize - self.base as usize;
+        let end = min(offset + max(size, 1), constants::BLOCK_CAPACITY);
+
+        for line_num in (offset / self.line_size)..=((end - 1) / self.line_size) {
+            self.mark(line_num).store(self.mark_generation, Ordering::Release);
+        }
+
+        self.mark(self.line_count).store(self.mark_generation, Ordering::Release);
+    }
+
+    pub fn line_of(&self, ptr: *const u8) -> usize {
+        (ptr as usize - self.base as usize) / self.line_size
+    }
+
+    pub fn is_line_marked_atomic(&self, line_num: usize) -> bool {
+        self.mark(line_num).load(Ordering::Acquire) == self.mark_generation
+    }
+}
+
 pub struct BumpBlock {
     block: Block,
     cursor: *const u8,
-    limit: *const u8
+    limit: *const u8,
+    hole_strategy: HoleStrategy,
+    object_map: [u64; constants::OBJECT_MAP_WORDS],
+    side_meta: Option<Box<[AtomicU8]>>,
+    line_size: usize,
+    mark_generation: u8,
+    age: u32,
+    hole_scans: usize,
+    // set once allocation leaves the hole the cursor was bumping
+    left_hole: bool,
 }
 
 impl BumpBlock {
+    #[cfg(any(feature = "std", test))]
     pub fn new() -> Result<BumpBlock, AllocError> {
-        let block = Block::new(constants::BLOCK_SIZE)?;
+        Ok(BumpBlock::from_block(Block::new(constants::BLOCK_SIZE)?))
+    }
+
+    pub fn new_in(source: Option<Arc<dyn BlockSource>>) -> Result<BumpBlock, AllocError> {
+        let block = Block::new_in(constants::BLOCK_SIZE, source)?;
+
+        Ok(BumpBlock::from_block(block))
+    }
+
+    // `block` must be BLOCK_SIZE; its contents are wiped
+    pub fn from_block(block: Block) -> BumpBlock {
+        debug_assert!(block.size() == constants::BLOCK_SIZE);
+
         let limit = block.as_ptr();
         let cursor = unsafe { limit.add(constants::BLOCK_CAPACITY) };
-        let mut bump_block = BumpBlock { block, cursor, limit};
+        let hole_strategy = HoleStrategy::FirstFit;
+        let object_map = [0; constants::OBJECT_MAP_WORDS];
+        let mut bump_block = BumpBlock { block, cursor, limit, hole_strategy, object_map, side_meta: None,
+            line_size: constants::LINE_SIZE, mark_generation: constants::MARKED, age: 0,
+            hole_scans: 0, left_hole: false };
 
         bump_block.reset();
 
-        Ok(bump_block)
+        bump_block
     }
 
+    pub fn into_block(self) -> Block {
+        self.block
+    }
+
+    #[cfg(test)]
     pub fn inner_alloc(&mut self, alloc_size: usize) -> Option<*const u8> {
+        self.inner_alloc_aligned(alloc_size, constants::ALLOC_ALIGN, 0)
+    }
+
+    // aligns the returned pointer so that `ptr + payload_offset` is a
+    // multiple of `align`, which must be a power of two >= ALLOC_ALIGN
+    pub fn inner_alloc_aligned(
+        &mut self,
+        alloc_size: usize,
+        align: usize,
+        payload_offset: usize,
+    ) -> Option<*const u8> {
         let ptr = self.cursor as usize;
         let limit = self.limit as usize;
-        let next_ptr = ptr.checked_sub(alloc_size)? & constants::ALLOC_ALIGN_MASK;
-
-        if next_ptr < limit {
-            let block_relative_limit =
-                unsafe { self.limit.sub(self.block.as_ptr() as usize) } as usize;
+        // underflowing just means the current hole is too small, so a lower
+        // hole that fits is still searched for
+        let next_ptr = ptr
+            .checked_sub(alloc_size)
+            .and_then(|start| ((start + payload_offset) & !(align - 1)).checked_sub(payload_offset))
+            .filter(|next_ptr| *next_ptr >= limit);
+
+        if let Some(next_ptr) = next_ptr {
+            self.cursor = next_ptr as *const u8;
+            self.set_object_start(next_ptr - self.block.as_ptr() as usize);
+            Some(self.cursor)
+        } else {
+            let block_relative_limit = self.offset_of(self.limit);
 
             if block_relative_limit > 0 {
+                self.hole_scans += 1;
+
                 if let Some((cursor, limit)) = self
                     .find_next_available_hole(block_relative_limit, alloc_size)
                 {
                     self.cursor = unsafe { self.block.as_ptr().add(cursor) };
                     self.limit = unsafe { self.block.as_ptr().add(limit) };
-                    return self.inner_alloc(alloc_size);
+                    self.left_hole = true;
+                    self.clear_object_starts(limit, cursor);
+                    return self.inner_alloc_aligned(alloc_size, align, payload_offset);
                 }
             }
 
             None
-        } else {
-            self.cursor = next_ptr as *const u8;
-            Some(self.cursor)
         }
     }
 
+    // carves `alloc_size` out of the top of the next hole below the current
+    // one, leaving the cursor in the current hole. the lines it takes are
+    // marked, so the cursor steps over them once it gets that far down.
+    pub fn alloc_in_lower_hole(
+        &mut self,
+        alloc_size: usize,
+        align: usize,
+        payload_offset: usize,
+    ) -> Option<*const u8> {
+        let below = self.offset_of(self.limit);
+
+        // a fresh block is a single hole, with nothing under it to search
+        if below == 0 {
+            return None;
+        }
+
+        self.hole_scans += 1;
+
+        let (cursor, limit) = self.find_next_available_hole(below, alloc_size)?;
+        let payload = (cursor.checked_sub(alloc_size)? + payload_offset) & !(align - 1);
+        let offset = payload.checked_sub(payload_offset).filter(|offset| *offset >= limit)?;
+
+        self.clear_object_starts(offset, cursor);
+        self.set_object_start(offset);
+        self.mark_object_at(offset, alloc_size);
+        self.left_hole = true;
+
+        Some(unsafe { self.block.as_ptr().add(offset) })
+    }
+
+    fn set_object_start(&mut self, offset: usize) {
+        let granule = offset / constants::ALLOC_ALIGN;
+
+        self.object_map[granule / 64] |= 1 << (granule % 64);
+    }
+
+    fn clear_object_starts(&mut self, from: usize, to: usize) {
+        for granule in (from / constants::ALLOC_ALIGN)..(to / constants::ALLOC_ALIGN) {
+            self.object_map[granule / 64] &= !(1 << (granule % 64));
+        }
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = *const u8> + '_ {
+        (0..constants::BLOCK_CAPACITY / constants::ALLOC_ALIGN)
+            .filter(|granule| self.object_map[granule / 64] & (1 << (granule % 64)) != 0)
+            .map(|granule| unsafe { self.block.as_ptr().add(granule * constants::ALLOC_ALIGN) })
+    }
+
+    // the closest recorded object start at or below `ptr`
+    pub fn object_start_before(&self, ptr: *const u8) -> Option<*const u8> {
+        let granule = self.offset_of(ptr) / constants::ALLOC_ALIGN;
+        let mut word_index = granule / 64;
+        let bit = granule % 64;
+        let mut word = self.object_map[word_index] & (u64::MAX >> (63 - bit));
+
+        loop {
+            if word != 0 {
+                let start = word_index * 64 + 63 - word.leading_zeros() as usize;
+
+                return Some(unsafe { self.block.as_ptr().add(start * constants::ALLOC_ALIGN) });
+            }
+
+            if word_index == 0 {
+                return None;
+            }
+
+            word_index -= 1;
+            word = self.object_map[word_index];
+        }
+    }
+
+    // the next hole below `starting_at` with room for `alloc_size`, as a
+    // (cursor, limit) pair of offsets to bump down from. lines are scanned
+    // from just below `starting_at` towards line 0, the way the cursor
+    // moves, so a hole above `starting_at` is never returned. the line above
+    // each marked line is skipped, in case an object there ran over into it.
+    // first fit returns the nearest hole; best fit the smallest one below.
     fn find_next_available_hole(
         &self,
         starting_at: usize,
         alloc_size: usize,
     ) -> Option<(usize, usize)> {
         let mut count = 0;
-        let starting_line = starting_at / constants::LINE_SIZE;
-        let lines_required = (alloc_size + constants::LINE_SIZE - 1) / constants::LINE_SIZE;
+        // the top line can run short of a whole line, so it counts as soon as
+        // `starting_at` reaches the end of the block
+        let starting_line = min(starting_at.div_ceil(self.line_size), self.line_count());
+        let lines_required = alloc_size.div_ceil(self.line_size);
         let mut end = starting_line;
+        let mut best: Option<(usize, usize)> = None;
 
         for index in (0..starting_line).rev() {
-            let marked = unsafe { *self.block.as_ptr().add(constants::META_OFFSET + index) };
+            let marked = self.mark(index).load(Ordering::Relaxed);
 
-            if marked == 0 {
+            let hole = if marked != self.mark_generation {
                 count += 1;
 
                 if index == 0 && count >= lines_required {
                     let limit = 0;
-                    let cursor = end * constants::LINE_SIZE;
-                    return Some((cursor, limit));
+                    let cursor = self.line_offset(end);
+                    Some((cursor, limit))
+                } else {
+                    None
                 }
             } else {
-                if count > lines_required {
-                    let limit = (index + 2) * constants::LINE_SIZE;
-                    let cursor = end * constants::LINE_SIZE;
-                    return Some((cursor, limit));
-                }
+                let hole = if count > lines_required {
+                    let limit = self.line_offset(index + 2);
+                    let cursor = self.line_offset(end);
+                    Some((cursor, limit))
+                } else {
+                    None
+                };
 
                 count = 0;
                 end = index;
+
+                hole
+            };
+
+            if let Some((cursor, limit)) = hole {
+                match self.hole_strategy {
+                    HoleStrategy::FirstFit => return hole,
+
+                    HoleStrategy::BestFit => {
+                        if best.is_none_or(|(c, l)| cursor - limit < c - l) {
+                            best = hole;
+                        }
+                    }
+                }
             }
         }
 
-        None
+        best
+    }
+
+    pub fn set_hole_strategy(&mut self, hole_strategy: HoleStrategy) {
+        self.hole_strategy = hole_strategy;
     }
 
     pub fn mark_line(&mut self, line_num: usize) {
-        if constants::LINE_COUNT <= line_num {
+        if self.line_count() <= line_num {
             panic!("ALLOC ERROR: tried marking non existent line");
         }
 
-        let line_marker = unsafe { self.block.as_ptr().add(constants::META_OFFSET + line_num) as *mut u8 };
-
-        unsafe { *line_marker = constants::MARKED; };
+        self.mark(line_num).store(self.mark_generation, Ordering::Relaxed);
     }
 
     pub fn mark_block(&mut self) {
-        let block_marker = unsafe { self.block.as_ptr().add(constants::BLOCK_SIZE - 1) as *mut u8 };
+        self.mark(self.line_count()).store(self.mark_generation, Ordering::Relaxed);
+    }
+
+    // the view stays valid until the block is dropped or its line size or
+    // side metadata changes
+    pub fn atomic_marks(&self) -> AtomicMarks {
+        AtomicMarks {
+            base: self.block.as_ptr(),
+            meta: self.meta() as *const AtomicU8,
+            line_size: self.line_size,
+            line_count: self.line_count(),
+            mark_generation: self.mark_generation,
+        }
+    }
+
+    // marks written under any other generation stop counting
+    pub fn set_mark_generation(&mut self, mark_generation: u8) {
+        debug_assert!(mark_generation != constants::FREE);
+
+        self.mark_generation = mark_generation;
+    }
+
+    // one byte per line. a line counts as marked while its byte equals
+    // mark_generation(), anything else leaves it free for allocation.
+    pub fn metadata(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.meta(), self.line_count()) }
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.meta_mut(), self.line_count()) }
+    }
+
+    pub fn mark_generation(&self) -> u8 {
+        self.mark_generation
+    }
+
+    // frees every line and the block mark, leaving the cursor and limit
+    // where they were
+    pub fn clear_marks(&mut self) {
+        let meta_len = self.meta_len();
+
+        unsafe { self.meta_mut().write_bytes(constants::FREE, meta_len) };
+    }
+
+    pub fn base(&self) -> *const u8 {
+        self.block.as_ptr()
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        Block::base_of(ptr) == self.block.as_ptr() && self.offset_of(ptr) < constants::BLOCK_CAPACITY
+    }
+
+    pub fn mark_span(&mut self, ptr: *const u8, size: usize) {
+        self.mark_object_at(self.offset_of(ptr), size);
+    }
+
+    // marks the lines of a `size` byte object `offset` bytes into the block,
+    // and the block, for collectors that track live objects themselves
+    pub fn mark_object_at(&mut self, offset: usize, size: usize) {
+        for line_num in (offset / self.line_size)..=((offset + size - 1) / self.line_size) {
+            self.mark_line(line_num);
+        }
+
+        self.mark_block();
+    }
+
+    pub fn offset_of(&self, ptr: *const u8) -> usize {
+        ptr as usize - self.block.as_ptr() as usize
+    }
+
+    pub fn is_marked(&self) -> bool {
+        let block_marker = self.mark(self.line_count()).load(Ordering::Relaxed);
 
-        unsafe { *block_marker = constants::MARKED; };
+        block_marker == self.mark_generation
+    }
+
+    pub fn is_line_marked(&self, line_num: usize) -> bool {
+        let line_marker = self.mark(line_num).load(Ordering::Relaxed);
+
+        line_marker == self.mark_generation
+    }
+
+    pub fn has_marked_lines(&self) -> bool {
+        (0..self.line_count()).any(|line_num| self.is_line_marked(line_num))
+    }
+
+    // (offset, size) of every run of free lines, lowest first. like the hole
+    // search, the line after a marked line is left out of the run.
+    pub fn holes(&self) -> Vec<(usize, usize)> {
+        let mut holes = Vec::new();
+        let mut start = 0;
+
+        // line_count() stands in for a marked line past the end of the block
+        for line_num in 0..=self.line_count() {
+            if line_num < self.line_count() && !self.is_line_marked(line_num) {
+                continue;
+            }
+
+            if start < line_num {
+                holes.push((self.line_offset(start), self.line_offset(line_num) - self.line_offset(start)));
+            }
+
+            start = line_num + 2;
+        }
 
+        holes
+    }
+
+    // the most one allocation could take: the rest of the current hole, or
+    // a hole further down
+    pub fn max_hole(&self) -> usize {
+        let limit = self.offset_of(self.limit);
+        let lower = self
+            .holes()
+            .into_iter()
+            .filter(|(offset, _)| *offset < limit)
+            .map(|(offset, size)| min(offset + size, limit) - offset)
+            .max();
+
+        max(self.current_hole_size(), lower.unwrap_or(0))
+    }
+
+    pub fn occupied_lines(&self) -> usize {
+        (0..self.line_count()).filter(|line_num| self.is_line_marked(*line_num)).count()
+    }
+
+    pub fn has_hole(&self) -> bool {
+        self.find_next_available_hole(constants::BLOCK_CAPACITY, 1).is_some()
+    }
+
+    // unlike has_hole, this goes by the cursor, so lines bumped since the
+    // last mark phase count as taken
+    pub fn has_room(&self) -> bool {
+        self.current_hole_size() > 0 || self.find_next_available_hole(self.offset_of(self.limit), 1).is_some()
+    }
+
+    pub fn rewind(&mut self) {
+        self.cursor = unsafe { self.block.as_ptr().add(constants::BLOCK_CAPACITY) };
+        self.limit = self.cursor;
     }
 
     pub fn reset(&mut self) {
         self.limit = self.block.as_ptr();
         self.cursor = unsafe { self.limit.add(constants::BLOCK_CAPACITY) };
+        self.object_map = [0; constants::OBJECT_MAP_WORDS];
+        self.age = 0;
+        self.clear_marks();
+    }
+
+    // touches every page of the block, so the first allocations into it
+    // don't fault. each byte written is the one already there.
+    pub fn prefault(&self) {
+        for offset in (0..self.block.size()).step_by(constants::PAGE_SIZE) {
+            unsafe {
+                let byte = self.block.as_ptr().add(offset) as *mut MaybeUninit<u8>;
 
-        unsafe {
-            for i in 0..128 {
-                 *(self.block.as_ptr().add(constants::META_OFFSET + i) as *mut u8)
-                     = constants::FREE;
+                write_volatile(byte, read_volatile(byte));
             }
         }
     }
 
+    // hole searches since the last call, for the heap's running total
+    pub fn take_hole_scans(&mut self) -> usize {
+        take(&mut self.hole_scans)
+    }
+
+    // whether allocation has left the bumped hole since the last call
+    pub fn take_left_hole(&mut self) -> bool {
+        take(&mut self.left_hole)
+    }
+
+    // how many sweeps have kept this block since it was last reset
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+
+    pub fn survive(&mut self) {
+        self.age = self.age.saturating_add(1);
+    }
+
+    // the line and block marks live either inline at META_OFFSET or in a
+    // side table entry, keeping mark writes off the object pages
+    fn meta(&self) -> *const u8 {
+        match self.side_meta {
+            Some(ref meta) => meta.as_ptr() as *const u8,
+            None => unsafe { self.block.as_ptr().add(constants::META_OFFSET) },
+        }
+    }
+
+    fn meta_mut(&mut self) -> *mut u8 {
+        match self.side_meta {
+            Some(ref mut meta) => meta.as_mut_ptr() as *mut u8,
+            None => unsafe { self.block.as_ptr().add(constants::META_OFFSET) as *mut u8 },
+        }
+    }
+
+    // every mark byte is read and written through this, so marking from
+    // another thread through atomic_marks doesn't race the block
+    fn mark(&self, index: usize) -> &AtomicU8 {
+        unsafe { AtomicU8::from_ptr(self.meta().add(index) as *mut u8) }
+    }
+
+    pub fn use_side_metadata(&mut self) {
+        if !self.has_side_metadata() {
+            let meta = (0..self.meta_len()).map(|index| AtomicU8::new(self.mark(index).load(Ordering::Relaxed))).collect();
+
+            self.side_meta = Some(meta);
+        }
+    }
+
+    pub fn line_size(&self) -> usize {
+        self.line_size
+    }
+
+    // a short top line covers whatever capacity is left over
+    pub fn line_count(&self) -> usize {
+        constants::BLOCK_CAPACITY.div_ceil(self.line_size)
+    }
+
+    fn line_offset(&self, line_num: usize) -> usize {
+        min(line_num * self.line_size, constants::BLOCK_CAPACITY)
+    }
+
+    // a mark byte per line, then the block mark
+    fn meta_len(&self) -> usize {
+        self.line_count() + 1
+    }
+
+    // `line_size` is a power of two. the block has to be empty, as its
+    // marks are wiped. lines under LINE_SIZE need more marks than fit inline,
+    // so they always use side metadata.
+    pub fn set_line_size(&mut self, line_size: usize) {
+        debug_assert!(line_size.is_power_of_two());
+
+        if line_size == self.line_size {
+            return;
+        }
+
+        if self.has_side_metadata() || line_size < constants::LINE_SIZE {
+            self.side_meta = Some((0..=constants::BLOCK_CAPACITY.div_ceil(line_size)).map(|_| AtomicU8::new(constants::FREE)).collect());
+        }
+
+        self.line_size = line_size;
+        self.clear_marks();
+    }
+
+    pub fn has_side_metadata(&self) -> bool {
+        self.side_meta.is_some()
+    }
+
+    #[cfg(test)]
     unsafe fn write<T>(&mut self, object: T, offset: usize) -> *const T {
         let p = self.block.as_ptr().add(offset) as *mut T;
-        write(p, object);
+        core::ptr::write(p, object);
         p
     }
 
+    // forgets the object at `ptr`, and gives its space back if it was the
+    // last thing bumped
+    pub fn rollback(&mut self, ptr: *const u8, alloc_size: usize) {
+        self.forget_object(ptr);
+
+        if self.cursor == ptr {
+            self.cursor = unsafe { ptr.add(alloc_size) };
+        }
+    }
+
+    // the object at `ptr` stops being walked, without freeing its space
+    pub fn forget_object(&mut self, ptr: *const u8) {
+        let offset = self.offset_of(ptr);
+
+        self.clear_object_starts(offset, offset + constants::ALLOC_ALIGN);
+    }
+
+    // undoes forget_object, for a freed slot taken back in place
+    pub fn restore_object(&mut self, ptr: *const u8) {
+        self.set_object_start(self.offset_of(ptr));
+    }
+
+    // slides `live`, (start, alloc_size, align, aligned_offset) in address
+    // order, up against the end of the block and forgets every other object.
+    // each keeps `align` at `aligned_offset` past its start, as when it was
+    // allocated. the lines are remarked to cover just those objects and
+    // bumping resumes right below them. returns the new starts, in order.
+    pub fn compact(&mut self, live: &[(*const u8, usize, usize, usize)]) -> Vec<*const u8> {
+        let mut top = constants::BLOCK_CAPACITY;
+        let mut moved: Vec<*const u8> = live.iter().map(|(start, ..)| *start).collect();
+
+        self.object_map = [0; constants::OBJECT_MAP_WORDS];
+        self.clear_marks();
+
+        // highest first, so each copy only overwrites space already moved
+        // out of. rounding down never passes the old start, which is aligned.
+        for (index, (start, alloc_size, align, aligned_offset)) in live.iter().enumerate().rev() {
+            top = ((top - alloc_size + aligned_offset) & !(align - 1)) - aligned_offset;
+
+            unsafe { copy(*start, self.block.as_ptr().add(top) as *mut u8, *alloc_size) };
+
+            self.set_object_start(top);
+            moved[index] = unsafe { self.block.as_ptr().add(top) };
+        }
+
+        if top < constants::BLOCK_CAPACITY {
+            self.mark_object_at(top, constants::BLOCK_CAPACITY - top);
+        }
+
+        self.limit = self.block.as_ptr();
+        self.cursor = unsafe { self.limit.add(top) };
+
+        moved
+    }
+
     pub fn current_hole_size(&self) -> usize {
         self.cursor as usize - self.limit as usize
     }
+
+    pub fn cursor(&self) -> *const u8 {
+        self.cursor
+    }
+
+    pub fn limit(&self) -> *const u8 {
+        self.limit
+    }
+
+    // puts the bounds back to an earlier (cursor, limit) of this block,
+    // forgetting objects allocated in that range since
+    pub fn rewind_to(&mut self, cursor: *const u8, limit: *const u8) {
+        self.cursor = cursor;
+        self.limit = limit;
+        self.clear_object_starts(self.offset_of(limit), self.offset_of(cursor));
+    }
+
+    /// Moves the cursor down after an inline fast path has bumped it, and
+    /// records an object header starting at the new cursor.
+    ///
+    /// # Safety
+    ///
+    /// `cursor` must be `ALLOC_ALIGN` aligned, between `limit()` and
+    /// `cursor()`, and a valid header for one object must be written at it
+    /// before the next collection.
+    pub unsafe fn publish_cursor(&mut self, cursor: *const u8) {
+        debug_assert!(cursor >= self.limit && cursor <= self.cursor);
+        debug_assert!((cursor as usize).is_multiple_of(constants::ALLOC_ALIGN));
+
+        self.cursor = cursor;
+        self.set_object_start(self.offset_of(cursor));
+    }
 }
 
 #[cfg(test)]
@@ -174,6 +705,98 @@ mod tests {
         assert!(got == expect);
     }
 
+    #[test]
+    fn test_find_next_hole_other_line_sizes() {
+        for line in [64, 256] {
+            let mut block = BumpBlock::new().unwrap();
+
+            block.set_line_size(line);
+
+            assert!(block.line_count() == constants::BLOCK_CAPACITY.div_ceil(line));
+            assert!(block.has_side_metadata() == (line < constants::LINE_SIZE));
+            assert!(block.find_next_available_hole(constants::BLOCK_CAPACITY, 1) == Some((constants::BLOCK_CAPACITY, 0)));
+
+            block.mark_line(4);
+            block.mark_line(10);
+            block.mark_line(block.line_count() - 1);
+            block.mark_block();
+
+            assert!(block.occupied_lines() == 3);
+            assert!(block.find_next_available_hole(10 * line, line) == Some((10 * line, 6 * line)));
+            assert!(block.find_next_available_hole(10 * line, 6 * line).is_none());
+            assert!(block.find_next_available_hole(4 * line, line) == Some((4 * line, 0)));
+
+            let top = (block.line_count() - 1) * line;
+
+            assert!(block.find_next_available_hole(constants::BLOCK_CAPACITY, line) == Some((top, 12 * line)));
+            assert!(block.holes() == vec![(0, 4 * line), (6 * line, 4 * line), (12 * line, top - 12 * line)]);
+
+            block.reset();
+
+            let alloc_size = 3 * line / 2;
+
+            for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
+                assert!(block.inner_alloc(alloc_size).is_some());
+            }
+
+            assert!(block.inner_alloc(alloc_size).is_none());
+        }
+    }
+
+    #[test]
+    fn test_find_next_hole_matrix() {
+        let line = constants::LINE_SIZE;
+        let above_only: Vec<usize> = (0..=20).chain([60]).collect();
+        let below_only: Vec<usize> = [20].into_iter().chain(30..constants::LINE_COUNT).collect();
+        let both = vec![5, 30, 31, 60];
+        let cases = [
+            (&above_only, 21, 1, HoleStrategy::FirstFit, None),
+            (&above_only, 21, 1, HoleStrategy::BestFit, None),
+            (&below_only, 30, 1, HoleStrategy::FirstFit, Some((30 * line, 22 * line))),
+            (&below_only, 30, 8, HoleStrategy::FirstFit, Some((30 * line, 22 * line))),
+            (&below_only, 30, 9, HoleStrategy::FirstFit, Some((20 * line, 0))),
+            (&below_only, 30, 1, HoleStrategy::BestFit, Some((30 * line, 22 * line))),
+            (&below_only, 30, 21, HoleStrategy::FirstFit, None),
+            (&both, 30, 1, HoleStrategy::FirstFit, Some((30 * line, 7 * line))),
+            (&both, 30, 1, HoleStrategy::BestFit, Some((5 * line, 0))),
+            (&both, 30, 10, HoleStrategy::BestFit, Some((30 * line, 7 * line))),
+            (&both, 30, 24, HoleStrategy::FirstFit, None),
+            (&both, 30, 24, HoleStrategy::BestFit, None),
+            (&both, 45, 1, HoleStrategy::FirstFit, Some((45 * line, 33 * line))),
+            (&both, 45, 13, HoleStrategy::FirstFit, Some((30 * line, 7 * line))),
+        ];
+
+        for (marks, start_line, lines, hole_strategy, expect) in cases {
+            let mut block = BumpBlock::new().unwrap();
+
+            block.set_hole_strategy(hole_strategy);
+
+            for line_num in marks {
+                block.mark_line(*line_num);
+            }
+
+            assert!(block.find_next_available_hole(start_line * line, lines * line) == expect);
+        }
+    }
+
+    #[test]
+    fn test_mark_object_at() {
+        let mut block = BumpBlock::new().unwrap();
+
+        // spans lines 2 and 3, then exactly line 10
+        block.mark_object_at(2 * constants::LINE_SIZE + 16, 200);
+        block.mark_object_at(10 * constants::LINE_SIZE, constants::LINE_SIZE);
+
+        assert!(block.is_marked());
+        assert!(block.occupied_lines() == 3);
+
+        let line = constants::LINE_SIZE;
+
+        assert!(block.find_next_available_hole(20 * line, line) == Some((20 * line, 12 * line)));
+        assert!(block.find_next_available_hole(10 * line, line) == Some((10 * line, 5 * line)));
+        assert!(block.find_next_available_hole(3 * line, line) == Some((2 * line, 0)));
+    }
+
     #[test]
     fn test_find_next_hole_at_line_zero() {
         let mut block = BumpBlock::new().unwrap();
@@ -194,6 +817,25 @@ mod tests {
         assert!(got == expect);
     }
 
+    #[test]
+    fn test_alloc_too_big_for_low_hole_searches_below() {
+        let mut block = BumpBlock::new().unwrap();
+
+        for line_num in 5..constants::LINE_COUNT {
+            if line_num != 9 {
+                block.mark_line(line_num);
+            }
+        }
+
+        block.cursor = unsafe { block.block.as_ptr().add(10 * constants::LINE_SIZE) };
+        block.limit = unsafe { block.block.as_ptr().add(9 * constants::LINE_SIZE) };
+
+        let ptr = block.inner_alloc(3 * constants::LINE_SIZE).unwrap();
+
+        assert!(ptr == unsafe { block.block.as_ptr().add(2 * constants::LINE_SIZE) });
+        assert!(block.limit == block.block.as_ptr());
+    }
+
     #[test]
     fn test_find_next_hole_at_block_end() {
         let mut block = BumpBlock::new().unwrap();
@@ -234,7 +876,7 @@ mod tests {
             got
         );
 
-        assert!(got == None);
+        assert!(got.is_none());
     }
 
 
@@ -250,13 +892,66 @@ mod tests {
         assert!(got == expect);
     }
 
+    fn mark_holes_of_2_5_3_lines(block: &mut BumpBlock) {
+        // scanning down from the top: a 2 line hole, a 5 line hole, then a
+        // 3 line hole touching line 0. the line above each mark is skipped.
+        block.mark_line(3);
+        block.mark_line(10);
+
+        for i in 14..constants::LINE_COUNT {
+            block.mark_line(i);
+        }
+    }
+
+    #[test]
+    fn test_first_fit_takes_first_hole_that_fits() {
+        let mut block = BumpBlock::new().unwrap();
+
+        mark_holes_of_2_5_3_lines(&mut block);
+
+        let expect = Some((10 * constants::LINE_SIZE, 5 * constants::LINE_SIZE));
+        let got = block.find_next_available_hole(constants::BLOCK_CAPACITY, 3 * constants::LINE_SIZE);
+
+        println!("test_first_fit_takes_first_hole_that_fits got {:?} expected {:?}", got, expect);
+
+        assert!(got == expect);
+    }
+
+    #[test]
+    fn test_best_fit_takes_smallest_hole_that_fits() {
+        let mut block = BumpBlock::new().unwrap();
+
+        mark_holes_of_2_5_3_lines(&mut block);
+        block.set_hole_strategy(HoleStrategy::BestFit);
+
+        let expect = Some((3 * constants::LINE_SIZE, 0));
+        let got = block.find_next_available_hole(constants::BLOCK_CAPACITY, 3 * constants::LINE_SIZE);
+
+        println!("test_best_fit_takes_smallest_hole_that_fits got {:?} expected {:?}", got, expect);
+
+        assert!(got == expect);
+    }
+
+    #[test]
+    fn test_best_fit_skips_holes_too_small() {
+        let mut block = BumpBlock::new().unwrap();
+
+        mark_holes_of_2_5_3_lines(&mut block);
+        block.set_hole_strategy(HoleStrategy::BestFit);
+
+        let expect = Some((10 * constants::LINE_SIZE, 5 * constants::LINE_SIZE));
+        let got = block.find_next_available_hole(constants::BLOCK_CAPACITY, 4 * constants::LINE_SIZE);
+
+        assert!(got == expect);
+    }
+
     #[test]
     fn test_mark_line_overflow_panics() {
         let mut block = BumpBlock::new().unwrap();
 
         block.mark_line(126); // line 126 is the last line
 
-        let result = std::panic::catch_unwind(move || block.mark_line(127));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || block.mark_line(127)));
 
         assert!(result.is_err());
     }
@@ -297,6 +992,20 @@ mod tests {
         assert!(ptr == unsafe { block.block.as_ptr().add(constants::BLOCK_CAPACITY - (size_of::<usize>() * 2)) });
     }
 
+    #[test]
+    fn test_alloc_aligned_payload() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.inner_alloc(8).unwrap();
+
+        for align in [16, 32, 64, 128] {
+            let ptr = block.inner_alloc_aligned(24, align, 8).unwrap();
+
+            assert!((ptr as usize + 8).is_multiple_of(align));
+            assert!(block.objects().any(|object| object == ptr));
+        }
+    }
+
     #[test]
     fn test_alloc_on_full_block() {
         let mut block = BumpBlock::new().unwrap();
@@ -314,6 +1023,142 @@ mod tests {
         assert!(ptr.is_none());
     }
 
+    #[test]
+    fn test_prefault_keeps_block_usable() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.mark_line(40);
+        block.prefault();
+
+        assert!(block.is_line_marked(40));
+        assert!(block.inner_alloc(constants::LINE_SIZE).is_some());
+
+        let ptr = unsafe { block.write(7u64, 0) };
+
+        block.prefault();
+
+        assert!(unsafe { *ptr } == 7);
+    }
+
+    #[test]
+    fn test_atomic_marks_while_allocating() {
+        for side_metadata in [false, true] {
+            for _ in 0..50 {
+                let mut block = BumpBlock::new().unwrap();
+
+                if side_metadata {
+                    block.use_side_metadata();
+                }
+
+                let marks = block.atomic_marks();
+                let base = block.base() as usize;
+                let line = constants::LINE_SIZE;
+
+                std::thread::scope(|scope| {
+                    scope.spawn(|| {
+                        for line_num in (0..constants::LINE_COUNT).step_by(2) {
+                            marks.mark_span_atomic((base + line_num * line) as *const u8, 1);
+                        }
+                    });
+
+                    // the block bumps and searches its own marks meanwhile
+                    while block.inner_alloc(line).is_some() {}
+                });
+
+                assert!((0..constants::LINE_COUNT).step_by(2).all(|line_num| block.is_line_marked(line_num)));
+                assert!((0..constants::LINE_COUNT).step_by(2).all(|line_num| marks.is_line_marked_atomic(line_num)));
+                assert!(block.is_marked());
+            }
+        }
+    }
+
+    #[test]
+    fn test_alloc_exact_block_capacity() {
+        let mut block = BumpBlock::new().unwrap();
+        let ptr = block.inner_alloc(constants::BLOCK_CAPACITY).unwrap();
+
+        assert!(ptr == block.base());
+        assert!(block.current_hole_size() == 0);
+        assert!(block.objects().collect::<Vec<_>>() == [ptr]);
+        assert!(block.inner_alloc(constants::ALLOC_ALIGN).is_none());
+        assert!(BumpBlock::new().unwrap().inner_alloc(constants::BLOCK_CAPACITY + 1).is_none());
+    }
+
+    #[test]
+    fn test_objects_tracks_allocations() {
+        let mut block = BumpBlock::new().unwrap();
+        let first = block.inner_alloc(16).unwrap();
+        let second = block.inner_alloc(200).unwrap();
+        let third = block.inner_alloc(8).unwrap();
+        let objects: Vec<*const u8> = block.objects().collect();
+
+        assert!(objects == vec![third, second, first]);
+
+        block.reset();
+
+        assert!(block.objects().count() == 0);
+    }
+
+    #[test]
+    fn test_objects_cleared_when_hole_reused() {
+        let mut block = BumpBlock::new().unwrap();
+
+        for _ in 0..constants::LINE_COUNT {
+            block.inner_alloc(constants::LINE_SIZE).unwrap();
+        }
+
+        block.mark_line(100);
+        block.cursor = unsafe { block.block.as_ptr().add(constants::BLOCK_CAPACITY) };
+        block.limit = block.cursor;
+
+        block.inner_alloc(8).unwrap();
+
+        let top_hole_start = unsafe { block.block.as_ptr().add(102 * constants::LINE_SIZE) };
+        let in_top_hole = block.objects().filter(|object| *object >= top_hole_start).count();
+
+        assert!(in_top_hole == 1);
+        assert!(block.objects().count() == 102 + 1);
+    }
+
+    #[test]
+    fn test_rewind_keeps_marks() {
+        let mut block = BumpBlock::new().unwrap();
+
+        for _ in 0..constants::LINE_COUNT {
+            block.inner_alloc(constants::LINE_SIZE).unwrap();
+        }
+
+        block.mark_line(120);
+        block.rewind();
+
+        assert!(block.has_marked_lines());
+        assert!(block.has_hole());
+
+        let ptr = block.inner_alloc(constants::LINE_SIZE).unwrap();
+
+        assert!(ptr == unsafe { block.block.as_ptr().add(constants::BLOCK_CAPACITY - constants::LINE_SIZE) });
+    }
+
+    #[test]
+    fn test_metadata_mut_writes_line_marks() {
+        let mut block = BumpBlock::new().unwrap();
+        let line = constants::LINE_SIZE;
+        let mark_generation = block.mark_generation();
+
+        assert!(block.metadata().len() == constants::LINE_COUNT);
+        assert!(block.metadata().iter().all(|mark| *mark == constants::FREE));
+
+        block.metadata_mut()[20] = mark_generation;
+
+        assert!(block.is_line_marked(20));
+        assert!(block.find_next_available_hole(30 * line, line) == Some((30 * line, 22 * line)));
+
+        block.metadata_mut()[20] = constants::FREE;
+
+        assert!(!block.has_marked_lines());
+        assert!(block.find_next_available_hole(30 * line, line) == Some((30 * line, 0)));
+    }
+
     #[test]
     fn test_reset() {
         let mut block = BumpBlock::new().unwrap();
@@ -333,4 +1178,137 @@ block.reset();
         assert!(block.current_hole_size() == (constants::BLOCK_CAPACITY - 8));
         assert!(ptr == unsafe { block.block.as_ptr().add(constants::BLOCK_CAPACITY - 8) });
     }
+
+    #[test]
+    fn test_clear_marks_keeps_cursor() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.inner_alloc(constants::LINE_SIZE * 3).unwrap();
+        block.mark_span(block.cursor, constants::LINE_SIZE * 3);
+        block.mark_block();
+
+        let (cursor, limit) = (block.cursor, block.limit);
+
+        block.clear_marks();
+
+        assert!(block.cursor == cursor);
+        assert!(block.limit == limit);
+        assert!(!block.is_marked());
+        assert!((0..constants::LINE_COUNT).all(|line_num| !block.is_line_marked(line_num)));
+    }
+
+    #[test]
+    fn test_publish_cursor() {
+        let mut block = BumpBlock::new().unwrap();
+        let limit = block.limit();
+        let cursor = unsafe { block.cursor().sub(32) };
+
+        assert!(cursor >= limit);
+
+        unsafe { block.publish_cursor(cursor) };
+
+        assert!(block.cursor() == cursor);
+        assert!(block.current_hole_size() == constants::BLOCK_CAPACITY - 32);
+        assert!(block.objects().collect::<Vec<_>>() == vec![cursor]);
+
+        let next = block.inner_alloc(16).unwrap();
+
+        assert!(next == unsafe { cursor.sub(16) });
+    }
+
+    #[test]
+    fn test_side_metadata_marks() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.mark_line(3);
+        block.use_side_metadata();
+
+        assert!(block.has_side_metadata());
+        assert!(block.is_line_marked(3));
+
+        block.mark_line(5);
+        block.mark_block();
+        block.metadata_mut()[3] = constants::FREE;
+
+        let inline = unsafe { block.block.as_ptr().add(constants::META_OFFSET) };
+
+        assert!(block.is_line_marked(5));
+        assert!(!block.is_line_marked(3));
+        assert!(block.is_marked());
+        assert!(unsafe { *inline.add(3) } == constants::MARKED);
+        assert!(unsafe { *inline.add(5) } == constants::FREE);
+        assert!(unsafe { *inline.add(constants::LINE_COUNT) } == constants::FREE);
+
+        block.reset();
+
+        assert!(!block.has_marked_lines());
+        assert!(!block.is_marked());
+    }
+
+    #[test]
+    fn test_side_metadata_find_hole() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.use_side_metadata();
+
+        for line_num in 0..constants::LINE_COUNT {
+            if line_num != 60 && line_num != 61 && line_num != 62 {
+                block.mark_line(line_num);
+            }
+        }
+
+        block.rewind();
+
+        let ptr = block.inner_alloc(constants::LINE_SIZE).unwrap();
+
+        assert!(ptr == unsafe { block.block.as_ptr().add(62 * constants::LINE_SIZE) });
+    }
+
+    #[test]
+    fn test_stale_generation_marks_are_ignored() {
+        let mut block = BumpBlock::new().unwrap();
+
+        block.mark_line(3);
+        block.mark_line(4);
+        block.mark_block();
+        block.set_mark_generation(constants::MARKED + 1);
+
+        assert!(!block.is_line_marked(3));
+        assert!(!block.is_marked());
+        assert!(!block.has_marked_lines());
+
+        block.mark_line(4);
+        block.mark_block();
+
+        assert!(block.is_line_marked(4));
+        assert!(!block.is_line_marked(3));
+        assert!(block.is_marked());
+        assert!(block.occupied_lines() == 1);
+
+        // lines 0-3 are free again now that line 3's mark is stale, and the
+        // hole runs up to the still marked line 4
+        block.rewind();
+        block.limit = unsafe { block.block.as_ptr().add(5 * constants::LINE_SIZE) };
+        block.cursor = block.limit;
+
+        let ptr = block.inner_alloc(3 * constants::LINE_SIZE).unwrap();
+
+        assert!(ptr == unsafe { block.block.as_ptr().add(constants::LINE_SIZE) });
+    }
+
+    #[test]
+    fn test_holes() {
+        let mut block = BumpBlock::new().unwrap();
+
+        assert!(block.holes() == vec![(0, constants::BLOCK_CAPACITY)]);
+
+        block.mark_line(0);
+        block.mark_line(1);
+        block.mark_line(constants::LINE_COUNT - 1);
+
+        let holes = block.holes();
+        let size = (constants::LINE_COUNT - 4) * constants::LINE_SIZE;
+
+        assert!(holes == vec![(3 * constants::LINE_SIZE, size)]);
+    }
 }
@@ -1,18 +1,44 @@
-use std::mem::size_of;
+use core::mem::size_of;
 
+// The last META_SIZE bytes of a block are metadata: one mark byte per line
+// followed by a single block mark byte. A line is marked when a live object
+// occupies it. The block byte is marked when any object in the block is, so
+// a sweep can free a block whose block byte is clear without reading a
+// single line mark. Blocks using side metadata keep the same layout in a
+// separate table and leave these bytes unused.
+//
+// LINE_SIZE is only the default. A heap can pick any power of two line size
+// and its blocks then hold one mark byte per line of BLOCK_CAPACITY, the top
+// line cut short if need be. Lines under LINE_SIZE need more marks than the
+// inline bytes hold, so their blocks always use side metadata.
+//
+// Marking writes the block's current mark generation rather than a fixed
+// value. Bytes left over from older generations read as unmarked, so a new
+// mark phase doesn't need a clearing pass.
 pub const BLOCK_SIZE: usize = 1024 * 16;
 pub const LINE_SIZE: usize = 128;
 pub const META_SIZE: usize = BLOCK_SIZE / LINE_SIZE;
+#[cfg(test)]
 pub const LINE_COUNT: usize = META_SIZE - 1;
 pub const BLOCK_CAPACITY: usize = BLOCK_SIZE - META_SIZE;
 pub const META_OFFSET: usize = BLOCK_CAPACITY;
 
-pub const ALLOC_ALIGN_MASK: usize = !(size_of::<usize>() - 1);
+// the smallest page size blocks are backed by
+pub const PAGE_SIZE: usize = 4096;
+
+pub const ALLOC_ALIGN: usize = size_of::<usize>();
+pub const OBJECT_MAP_WORDS: usize = (BLOCK_CAPACITY / ALLOC_ALIGN).div_ceil(64);
+
+// with redzones on, every object is followed by REDZONE_SIZE bytes of
+// REDZONE_POISON, so a write past its end shows up in check_redzones
+#[cfg(any(feature = "std", test))]
+pub const REDZONE_SIZE: usize = 16;
+pub const REDZONE_POISON: u8 = 0xfd;
 
 pub const FREE: u8 = 0;
 pub const MARKED: u8 = 1;
  
-pub const MAX_ALLOC_SIZE: usize = std::u32::MAX as usize;
+pub const MAX_ALLOC_SIZE: usize = u32::MAX as usize;
 pub const SMALL_OBJECT_MIN: usize = 1;
 pub const SMALL_OBJECT_MAX: usize = LINE_SIZE;
 pub const MEDIUM_OBJECT_MIN: usize = SMALL_OBJECT_MAX + 1;
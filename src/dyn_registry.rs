@@ -0,0 +1,121 @@
+use alloc::boxed::Box;
+use core::hash::Hash;
+use core::ptr::NonNull;
+
+use crate::allocator::{AllocObject, AllocTypeId};
+#[cfg(not(feature = "std"))]
+use crate::type_map::TypeMap;
+
+#[cfg(feature = "std")]
+type TypeMap<K, V> = std::collections::HashMap<K, V>;
+
+type CastFn<D> = Box<dyn Fn(NonNull<()>) -> *const D>;
+
+// how to view each registered type as a `D`, usually some `dyn Trait`,
+// looked up by the type id in the object's header
+pub struct DynRegistry<I, D: ?Sized> {
+    casts: TypeMap<I, CastFn<D>>,
+}
+
+impl<I: AllocTypeId + Hash + Eq, D: ?Sized + 'static> DynRegistry<I, D> {
+    pub fn new() -> DynRegistry<I, D> {
+        DynRegistry { casts: TypeMap::new() }
+    }
+
+    // `cast` is normally just `|object| object`, letting the compiler do
+    // the unsizing
+    pub fn register<T>(&mut self, cast: fn(&T) -> &D)
+    where
+        T: AllocObject<I> + 'static,
+    {
+        let cast = move |object: NonNull<()>| cast(unsafe { &*(object.as_ptr() as *const T) }) as *const D;
+
+        self.casts.insert(T::TYPE_ID, Box::new(cast));
+    }
+
+    pub fn contains(&self, type_id: &I) -> bool {
+        self.casts.contains_key(type_id)
+    }
+
+    pub(crate) fn cast(&self, type_id: &I, object: NonNull<()>) -> Option<*const D> {
+        self.casts.get(type_id).map(|cast| cast(object))
+    }
+}
+
+impl<I: AllocTypeId + Hash + Eq, D: ?Sized + 'static> Default for DynRegistry<I, D> {
+    fn default() -> DynRegistry<I, D> {
+        DynRegistry::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::AllocRaw;
+    use crate::heap::tests::{TestHeader, TestTypeId};
+    use crate::heap::ZapHeap;
+
+    trait Shape {
+        fn area(&self) -> u32;
+    }
+
+    struct Square {
+        side: u32,
+    }
+
+    struct Rect {
+        width: u32,
+        height: u32,
+    }
+
+    impl Shape for Square {
+        fn area(&self) -> u32 {
+            self.side * self.side
+        }
+    }
+
+    impl Shape for Rect {
+        fn area(&self) -> u32 {
+            self.width * self.height
+        }
+    }
+
+    impl AllocObject<TestTypeId> for Square {
+        const TYPE_ID: TestTypeId = TestTypeId::Small;
+    }
+
+    impl AllocObject<TestTypeId> for Rect {
+        const TYPE_ID: TestTypeId = TestTypeId::Medium;
+    }
+
+    #[test]
+    fn test_dispatch_through_registry() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut shapes = DynRegistry::<TestTypeId, dyn Shape>::new();
+
+        shapes.register::<Square>(|square| square);
+        shapes.register::<Rect>(|rect| rect);
+
+        let square = heap.alloc_dyn(Square { side: 3 }, &shapes).unwrap().as_untyped();
+        let rect = heap.alloc_dyn(Rect { width: 2, height: 5 }, &shapes).unwrap().as_untyped();
+        let areas: Vec<u32> = [square, rect]
+            .iter()
+            .map(|object| unsafe { heap.get_dyn(*object, &shapes) }.unwrap().area())
+            .collect();
+
+        assert!(areas == [9, 10]);
+    }
+
+    #[test]
+    fn test_alloc_dyn_needs_registration() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut shapes = DynRegistry::<TestTypeId, dyn Shape>::new();
+
+        shapes.register::<Square>(|square| square);
+
+        let array = heap.alloc_array(8).unwrap().as_untyped();
+
+        assert!(heap.alloc_dyn(Rect { width: 2, height: 5 }, &shapes).err() == Some(crate::AllocError::BadRequest));
+        assert!(unsafe { heap.get_dyn(array, &shapes) }.is_none());
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+// an index into a heap's handle table. the table follows the object through
+// evacuation and relocation, so a handle stays good where a pointer wouldn't.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Handle(usize);
+
+pub(crate) struct HandleTable {
+    objects: Vec<NonNull<()>>,
+    by_object: BTreeMap<usize, usize>,
+}
+
+impl HandleTable {
+    pub(crate) fn new() -> HandleTable {
+        HandleTable {
+            objects: Vec::new(),
+            by_object: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, object: NonNull<()>) -> Handle {
+        let index = self.objects.len();
+
+        self.objects.push(object);
+        self.by_object.insert(object.as_ptr() as usize, index);
+
+        Handle(index)
+    }
+
+    pub(crate) fn get(&self, handle: Handle) -> NonNull<()> {
+        self.objects[handle.0]
+    }
+
+    pub(crate) fn repoint(&mut self, old: NonNull<()>, new: NonNull<()>) {
+        if let Some(index) = self.by_object.remove(&(old.as_ptr() as usize)) {
+            self.objects[index] = new;
+            self.by_object.insert(new.as_ptr() as usize, index);
+        }
+    }
+
+    // the handle dangles from here on, but nothing allocated at the same
+    // address later gets repointed along with it
+    pub(crate) fn forget(&mut self, object: NonNull<()>) {
+        self.by_object.remove(&(object.as_ptr() as usize));
+    }
+
+    // forget, for every object `live` turns down
+    pub(crate) fn forget_unless<F>(&mut self, live: F)
+    where
+        F: Fn(NonNull<()>) -> bool,
+    {
+        self.by_object.retain(|object, _| live(unsafe { NonNull::new_unchecked(*object as *mut ()) }));
+    }
+}
@@ -1,23 +1,326 @@
-use std::cell::UnsafeCell;
-use std::marker::PhantomData;
-use std::mem::{replace, size_of};
-use std::ptr::{write, NonNull};
-use std::slice::from_raw_parts_mut;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::{Cell, UnsafeCell};
+use core::cmp::{max, min, Reverse};
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::mem::{align_of, forget, size_of, take, MaybeUninit};
+#[cfg(feature = "debug-alloc-sites")]
+use core::panic::Location;
+use core::ptr::{copy_nonoverlapping, drop_in_place, read, slice_from_raw_parts, write, NonNull};
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+#[cfg(feature = "profiling")]
+use std::collections::HashMap;
+#[cfg(feature = "profiling")]
+use std::time::Instant;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
 
 use crate::allocator::{
-    add_alignment_padding, AllocError, AllocHeader, AllocObject, AllocRaw, ArraySize, Mark, SizeClass,
+    add_alignment_padding, AllocError, AllocHeader, AllocObject, AllocRaw, ArraySize, Mark, PointerLayout,
+    SizeClass, Trace,
 };
-use crate::bump_block::BumpBlock;
+use crate::block::{Block, BlockSource};
+use crate::bump_block::{AtomicMarks, BumpBlock, HoleStrategy};
 use crate::constants;
-use crate::raw_ptr::RawPtr;
+use crate::dyn_registry::DynRegistry;
+use crate::handle::{Handle, HandleTable};
+use crate::policy::{CollectDecision, CollectionPolicy, HeapStats};
+use crate::raw_ptr::{ArrayPtr, RawPtr};
+#[cfg(not(feature = "std"))]
+use crate::type_map::TypeMap;
+use crate::weak_ref::{WeakRef, WeakSlot};
+
+#[cfg(feature = "std")]
+type TypeMap<K, V> = std::collections::HashMap<K, V>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HeapError {
+    BadCursor(*const u8),
+    BadSizeClass(*const u8),
+    ObjectInMetadata(*const u8),
+    OverlappingObjects(*const u8),
+    UnmarkedLine(*const u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocOp {
+    Young,
+    Old,
+}
+
+// enough to redo an allocation in a fresh heap. `size` is the object size
+// without the header.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AllocEvent<T> {
+    pub op: AllocOp,
+    pub size: usize,
+    pub align: usize,
+    pub aligned_offset: usize,
+    pub type_id: T,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockRole {
+    Head,
+    Overflow,
+    Recycle,
+    Used,
+    Free,
+}
+
+// where alloc_into bumps. a recycle block is named by its base, as
+// dump_layout reports it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockTarget {
+    Head,
+    Overflow,
+    Recycle(*const u8),
+}
+
+// `marked_lines` follows the line marks from the last mark phase
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockLayout {
+    pub base: *const u8,
+    pub space: AllocOp,
+    pub role: BlockRole,
+    pub age: u32,
+    pub marked_lines: Vec<bool>,
+}
+
+// allocation times by powers of two: bucket `i` counts those that took
+// [2^i, 2^(i + 1)) ns, the last one everything slower. an allocation is slow
+// when it had to take a block.
+#[cfg(feature = "profiling")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyStats {
+    pub fast: [u64; 32],
+    pub slow: [u64; 32],
+}
+
+#[cfg(feature = "profiling")]
+impl LatencyStats {
+    fn record(&mut self, slow: bool, nanos: u64) {
+        let bucket = min(nanos.max(1).ilog2() as usize, self.fast.len() - 1);
+
+        match slow {
+            true => self.slow[bucket] += 1,
+            false => self.fast[bucket] += 1,
+        }
+    }
+
+    pub fn fast_count(&self) -> u64 {
+        self.fast.iter().sum()
+    }
+
+    pub fn slow_count(&self) -> u64 {
+        self.slow.iter().sum()
+    }
+}
+
+// allocation counts by type. `count` is picked where the type ids are known
+// to hash, so the allocation paths don't have to require it.
+#[cfg(feature = "profiling")]
+struct TypeHistogram<T> {
+    counts: HashMap<T, u64>,
+    count: fn(&mut HashMap<T, u64>, T),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AllocSample<T> {
+    pub size: usize,
+    pub type_id: T,
+}
+
+// keeps every `period`th allocation, dropping the oldest past `capacity`
+struct Sampler<T> {
+    period: usize,
+    countdown: usize,
+    capacity: usize,
+    samples: VecDeque<AllocSample<T>>,
+}
+
+impl<T> Sampler<T> {
+    fn record(&mut self, sample: AllocSample<T>) {
+        self.countdown -= 1;
+
+        if self.countdown > 0 {
+            return;
+        }
+
+        self.countdown = self.period;
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+}
+
+// an empty block taken out of one heap for another
+pub struct FreeBlock {
+    block: BumpBlock,
+}
+
+// recycled blocks, split on whether their biggest hole could take a medium
+// object, so medium allocations skip blocks that could only fail
+#[derive(Default)]
+struct RecycleList {
+    small: Vec<BumpBlock>,
+    medium: Vec<BumpBlock>,
+}
+
+impl RecycleList {
+    fn push(&mut self, block: BumpBlock) {
+        if block.max_hole() >= constants::MEDIUM_OBJECT_MIN {
+            self.medium.push(block);
+        } else {
+            self.small.push(block);
+        }
+    }
+
+    // small objects use up the small holes first
+    fn pop(&mut self) -> Option<BumpBlock> {
+        self.small.pop().or_else(|| self.medium.pop())
+    }
+
+    // holes can shrink after a block is bucketed, so they're checked again.
+    // small sizes try the small holes first, like pop.
+    fn pop_fitting(&mut self, size: usize) -> Option<BumpBlock> {
+        if size < constants::MEDIUM_OBJECT_MIN {
+            if let Some(index) = self.small.iter().rposition(|block| block.max_hole() >= size) {
+                return Some(self.small.remove(index));
+            }
+        }
+
+        let index = self.medium.iter().rposition(|block| block.max_hole() >= size)?;
+
+        Some(self.medium.remove(index))
+    }
+
+    fn append(&mut self, blocks: &mut Vec<BumpBlock>) {
+        for block in blocks.drain(..) {
+            self.push(block);
+        }
+    }
+
+    fn drain(&mut self) -> Vec<BumpBlock> {
+        let mut blocks = take(&mut self.small);
+
+        blocks.append(&mut self.medium);
+        blocks
+    }
+
+    fn len(&self) -> usize {
+        self.small.len() + self.medium.len()
+    }
+
+    #[cfg(test)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &BumpBlock> {
+        self.small.iter().chain(self.medium.iter())
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut BumpBlock> {
+        self.small.iter_mut().chain(self.medium.iter_mut())
+    }
+}
+
+// where a space's allocation had got to, as (base, cursor, limit) for the
+// bump blocks
+struct SpaceCheckpoint {
+    head: Option<(*const u8, *const u8, *const u8)>,
+    overflow: Option<(*const u8, *const u8, *const u8)>,
+    recycle: Vec<*const u8>,
+    used: usize,
+    large: usize,
+    bytes_allocated: usize,
+    hole_moves: usize,
+    scattered_allocs: usize,
+}
+
+pub struct Checkpoint {
+    nursery: SpaceCheckpoint,
+    tenured: SpaceCheckpoint,
+}
+
+// line marks for every block of both spaces as of when it was made, for
+// marking from another thread while the heap's own thread allocates. blocks
+// taken since aren't covered; whatever went into them is new anyway.
+pub struct AtomicMarker<'h> {
+    blocks: Vec<AtomicMarks>,
+    _heap: PhantomData<&'h ()>,
+}
+
+impl AtomicMarker<'_> {
+    // marks the lines `size` bytes from `ptr` cover, and their block. returns
+    // false if `ptr` isn't in one of the blocks.
+    pub fn mark_span(&self, ptr: *const u8, size: usize) -> bool {
+        match self.blocks.iter().find(|marks| marks.contains(ptr)) {
+            Some(marks) => {
+                marks.mark_span_atomic(ptr, size);
+
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    pub fn is_marked(&self, ptr: *const u8) -> bool {
+        let line_of = |marks: &AtomicMarks| marks.is_line_marked_atomic(marks.line_of(ptr));
+
+        self.blocks.iter().find(|marks| marks.contains(ptr)).is_some_and(line_of)
+    }
+}
+
+// blocks shared between threads need a lock, so pools want std
+#[cfg(feature = "std")]
+pub(crate) type BlockPool = Arc<Mutex<Vec<Block>>>;
+
+// large objects get a block each, outside the line mark scheme. the block
+// is mostly held to keep the allocation alive.
+struct LargeObject {
+    _block: Block,
+    header: *const u8,
+}
 
 struct BlockList {
     head: Option<BumpBlock>,
     overflow: Option<BumpBlock>,
     free: Vec<BumpBlock>,
-    recycle: Vec<BumpBlock>,
+    recycle: RecycleList,
     used: Vec<BumpBlock>,
-    large: Vec<()>
+    large: Vec<LargeObject>,
+    taken_large: Vec<LargeObject>,
+    moved_large: Vec<LargeObject>,
+    hole_strategy: HoleStrategy,
+    bytes_allocated: usize,
+    gc_threshold: Option<usize>,
+    #[cfg(feature = "std")]
+    pool: Option<BlockPool>,
+    side_metadata: bool,
+    conservative_lines: usize,
+    mark_generation: u8,
+    line_size: usize,
+    // times the head took a new block, and hole searches done while bumping
+    refills: usize,
+    overflow_refills: usize,
+    hole_scans: usize,
+    // times allocation left the hole a bump allocator was in, and
+    // allocations into a block that isn't bumping as head or overflow
+    hole_moves: usize,
+    scattered_allocs: usize,
+    source: Option<Arc<dyn BlockSource>>,
+    #[cfg(feature = "testing")]
+    fail_after: Option<usize>,
 }
 
 impl BlockList {
@@ -26,9 +329,28 @@ impl BlockList {
             head: None,
             overflow: None,
             free: Vec::new(),
-            recycle: Vec::new(),
+            recycle: RecycleList::default(),
             used: Vec::new(),
             large: Vec::new(),
+            taken_large: Vec::new(),
+            moved_large: Vec::new(),
+            hole_strategy: HoleStrategy::FirstFit,
+            bytes_allocated: 0,
+            gc_threshold: None,
+            #[cfg(feature = "std")]
+            pool: None,
+            side_metadata: false,
+            conservative_lines: 0,
+            mark_generation: constants::MARKED,
+            line_size: constants::LINE_SIZE,
+            refills: 0,
+            overflow_refills: 0,
+            hole_scans: 0,
+            hole_moves: 0,
+            scattered_allocs: 0,
+            source: None,
+            #[cfg(feature = "testing")]
+            fail_after: None,
         }
     }
 
@@ -42,467 +364,6410 @@ impl BlockList {
         count
     }
 
-    fn overflow_alloc(&mut self, alloc_size: usize) -> Result<*const u8, AllocError> {
-        assert!(alloc_size <= constants::BLOCK_CAPACITY);
+    fn overflow_alloc(
+        &mut self,
+        alloc_size: usize,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        if alloc_size > constants::BLOCK_CAPACITY {
+            return Err(AllocError::BadRequest);
+        }
 
         let space = match self.overflow {
             Some(ref mut overflow) => {
-                match overflow.inner_alloc(alloc_size) {
-                    Some(space) => space,
-
-                    None => {
-                        let free_block = if !self.free.is_empty() {
-                            self.free.pop().unwrap()
-                        } else {
-                            BumpBlock::new()?
-                        };
-
-                        let previous = replace(overflow, free_block);
+                let space = overflow.inner_alloc_aligned(alloc_size, align, payload_offset);
 
-                        self.recycle.push(previous);
+                self.hole_scans += overflow.take_hole_scans();
+                self.hole_moves += overflow.take_left_hole() as usize;
 
-                        overflow.inner_alloc(alloc_size).unwrap()
-                    }
+                match space {
+                    Some(space) => space,
+                    None => self.refill_overflow(alloc_size, align, payload_offset)?,
                 }
             }
 
-            None => {
-                let mut overflow = self.get_free_block()?;
-                let space = overflow
-                    .inner_alloc(alloc_size)
-                    .unwrap();
+            None => self.refill_overflow(alloc_size, align, payload_offset)?,
+        };
 
-                self.overflow = Some(overflow);
+        Ok(space)
+    }
 
-                space
+    // a recycled block with a hole that fits beats a fresh one
+    fn refill_overflow(
+        &mut self,
+        alloc_size: usize,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        let padded_size = alloc_size + align - constants::ALLOC_ALIGN;
+        let mut block = match self.recycle.pop_fitting(padded_size) {
+            Some(mut block) => {
+                block.set_hole_strategy(self.hole_strategy);
+                block
             }
-        } as *const u8;
 
-        Ok(space)
-    }
+            None => self.get_free_block()?,
+        };
+        let space = block.inner_alloc_aligned(alloc_size, align, payload_offset);
 
-    fn get_free_block(&mut self) -> Result<BumpBlock, AllocError> {
-        if !self.free.is_empty() {
-            Ok(self.free.pop().unwrap())
-        } else {
-            BumpBlock::new()
-        }
-    }
+        self.hole_scans += block.take_hole_scans();
+        self.hole_moves += block.take_left_hole() as usize;
 
-    fn get_recycle_block(&mut self) -> Result<BumpBlock, AllocError> {
-        if !self.recycle.is_empty() {
-            Ok(self.recycle.pop().unwrap())
-        } else if !self.free.is_empty() {
-            Ok(self.free.pop().unwrap())
-        } else {
-            BumpBlock::new()
+        if let Some(previous) = self.overflow.replace(block) {
+            self.recycle.push(previous);
         }
-    }
-}
 
-pub struct ZapHeap<H> {
-    blocks: UnsafeCell<BlockList>,
-    _header_type: PhantomData<*const H>,
-}
+        self.overflow_refills += 1;
 
-impl<H> ZapHeap<H> {
-    pub fn new() -> ZapHeap<H> {
-        ZapHeap {
-            blocks: UnsafeCell::new(BlockList::new()),
-            _header_type: PhantomData,
-        }
+        space.ok_or(AllocError::BadRequest)
     }
 
-    fn find_space(
-        &self,
+    fn find_aligned_space(
+        &mut self,
         alloc_size: usize,
         size_class: SizeClass,
+        align: usize,
+        payload_offset: usize,
     ) -> Result<*const u8, AllocError> {
-        let blocks = unsafe { &mut *self.blocks.get() };
-
         if size_class == SizeClass::Large {
+            return self.large_alloc(alloc_size, align, payload_offset);
+        }
+
+        // anything that can't fit an empty block would refill forever
+        let padded_size = alloc_size.saturating_add(align - constants::ALLOC_ALIGN);
+
+        if padded_size > constants::BLOCK_CAPACITY {
             return Err(AllocError::BadRequest);
         }
 
-        let space = match blocks.head {
-            Some(ref mut head) => {
-                if size_class == SizeClass::Medium && alloc_size > head.current_hole_size() {
-                    return blocks.overflow_alloc(alloc_size);
+        // each class bumps its own block, so neither evicts the other's. a
+        // head block hole below the one being bumped still takes medium
+        // objects before the overflow block does.
+        if size_class == SizeClass::Medium {
+            if let Some(ref mut head) = self.head {
+                let space = head.alloc_in_lower_hole(alloc_size, align, payload_offset);
+
+                self.hole_scans += head.take_hole_scans();
+                self.hole_moves += head.take_left_hole() as usize;
+
+                if let Some(space) = space {
+                    return Ok(space);
                 }
+            }
+
+            return self.overflow_alloc(alloc_size, align, payload_offset);
+        }
+
+        let space = match self.head {
+            Some(ref mut head) => {
+                let space = head.inner_alloc_aligned(alloc_size, align, payload_offset);
 
-                match head.inner_alloc(alloc_size) {
+                self.hole_scans += head.take_hole_scans();
+                self.hole_moves += head.take_left_hole() as usize;
+
+                match space {
                     Some(space) => space,
 
                     None => {
-                        let free_block = if !blocks.recycle.is_empty() {
-                            blocks.recycle.pop().unwrap()
-                        } else if !blocks.free.is_empty() {
-                            blocks.free.pop().unwrap()
-                        } else {
-                            BumpBlock::new()?
+                        let free_block = match self.recycle.pop() {
+                            Some(mut block) => {
+                                block.set_hole_strategy(self.hole_strategy);
+                                block
+                            }
+
+                            None => self.get_free_block()?,
                         };
 
-                        let previous = replace(head, free_block);
+                        if let Some(previous) = self.head.replace(free_block) {
+                            self.used.push(previous);
+                        }
 
-                        blocks.used.push(previous);
+                        self.refills += 1;
 
-                        return self.find_space(alloc_size, size_class);
+                        return self.find_aligned_space(alloc_size, size_class, align, payload_offset);
                     }
                 }
             }
 
             None => {
-                let mut head = blocks.get_free_block()?;
-                let space = head
-                    .inner_alloc(alloc_size)
-                    .unwrap();
+                let mut head = self.get_free_block()?;
+                let space = head.inner_alloc_aligned(alloc_size, align, payload_offset);
 
-                blocks.head = Some(head);
+                self.head = Some(head);
+                self.refills += 1;
 
-                space
+                space.ok_or(AllocError::BadRequest)?
             }
-        } as *const u8;
+        };
 
         Ok(space)
     }
-}
 
-impl<H: AllocHeader> AllocRaw for ZapHeap<H> {
-    type Header = H;
+    // tries the block holding `near` before the usual search
+    fn find_space_near(
+        &mut self,
+        near: *const u8,
+        alloc_size: usize,
+        size_class: SizeClass,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        let bumping = |block: &Option<BumpBlock>| block.as_ref().is_some_and(|block| block.contains(near));
+        let scattered = !bumping(&self.head) && !bumping(&self.overflow);
 
-    fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
-    where
-        T: AllocObject<<Self::Header as AllocHeader>::TypeId>,
-    {
-        let header_size = size_of::<Self::Header>();
-        let header_alloc_size = add_alignment_padding(header_size);
-        let object_size = size_of::<T>();
-        let total_size = header_alloc_size + object_size;
-        let alloc_size = add_alignment_padding(total_size);
-        let size_class = SizeClass::get_for_size(alloc_size)?;
-        let space = self.find_space(alloc_size, size_class)?;
-        let header = Self::Header::new::<T>(object_size as ArraySize, size_class, Mark::Allocated);
+        if size_class != SizeClass::Large {
+            if let Some(block) = self.block_for(near) {
+                let space = block.inner_alloc_aligned(alloc_size, align, payload_offset);
+                let (hole_scans, left_hole) = (block.take_hole_scans(), block.take_left_hole());
 
-        unsafe {
-            let object_space = space.offset(header_alloc_size as isize);
+                self.hole_scans += hole_scans;
+                self.hole_moves += left_hole as usize;
 
-            write(space as *mut Self::Header, header);
-            write(object_space as *mut T, object);
+                if let Some(space) = space {
+                    self.scattered_allocs += scattered as usize;
 
-            Ok(RawPtr::new(object_space as *const T))
+                    return Ok(space);
+                }
+            }
         }
+
+        self.find_aligned_space(alloc_size, size_class, align, payload_offset)
     }
 
-    fn alloc_array(&self, size_bytes: ArraySize) -> Result<RawPtr<u8>, AllocError> {
-        let header_size = size_of::<Self::Header>();
-        let header_alloc_size = add_alignment_padding(header_size);
-        let total_size = header_alloc_size + size_bytes as usize;
-        let alloc_size = add_alignment_padding(total_size);
-        let size_class = SizeClass::get_for_size(alloc_size)?;
-        let space = self.find_space(alloc_size, size_class)?;
-        let header = Self::Header::new_array(size_bytes, size_class, Mark::Allocated);
+    // a head with no hole that can take `bytes` is given up for a recycled
+    // block that has one, or else an empty block, so the next `bytes` of
+    // small objects bump without a refill
+    fn reserve(&mut self, bytes: usize) -> Result<(), AllocError> {
+        if bytes > constants::BLOCK_CAPACITY {
+            return Err(AllocError::BadRequest);
+        }
 
-        unsafe {
-            let array_space = space.offset(header_size as isize);
-            write(space as *mut Self::Header, header);
-            let array = from_raw_parts_mut(array_space as *mut u8, size_bytes as usize);
-            for byte in array {
-                *byte = 0;
+        if let Some(ref head) = self.head {
+            if head.max_hole() >= bytes {
+                return Ok(());
+            }
+        }
+
+        let block = match self.recycle.pop_fitting(bytes) {
+            Some(mut block) => {
+                block.set_hole_strategy(self.hole_strategy);
+                block
             }
 
-            Ok(RawPtr::new(array_space as *const u8))
+            None => self.get_free_block()?,
+        };
+
+        if let Some(previous) = self.head.replace(block) {
+            self.used.push(previous);
         }
-    }
 
-    fn get_header(object: NonNull<()>) -> NonNull<Self::Header> {
-        unsafe { NonNull::new_unchecked(object.cast::<Self::Header>().as_ptr().offset(-1)) }
+        self.refills += 1;
+
+        Ok(())
     }
 
-    fn get_object(header: NonNull<Self::Header>) -> NonNull<()> {
-        unsafe { NonNull::new_unchecked(header.as_ptr().offset(1).cast::<()>()) }
+    // the head keeps its cursor, since the lines it bumped through aren't
+    // marked yet
+    fn recycle_head(&mut self) {
+        match self.head.take() {
+            Some(head) if head.has_room() => self.recycle.push(head),
+            Some(head) => self.used.push(head),
+            None => {}
+        }
     }
-}
 
-impl<H> Default for ZapHeap<H> {
-    fn default() -> ZapHeap<H> {
-        ZapHeap::new()
+    #[cfg(feature = "profiling")]
+    fn blocks_taken(&self) -> usize {
+        self.refills + self.overflow_refills + self.large.len()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::allocator::{AllocTypeId, SizeClass};
+    // only bumps the chosen block, failing rather than refilling it
+    fn find_space_in(
+        &mut self,
+        target: BlockTarget,
+        alloc_size: usize,
+        size_class: SizeClass,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        if size_class == SizeClass::Large {
+            return Err(AllocError::BadRequest);
+        }
 
-    struct SmallTestObj {
-        data: u32,
+        let block = match target {
+            BlockTarget::Head => self.head.as_mut(),
+            BlockTarget::Overflow => self.overflow.as_mut(),
+            BlockTarget::Recycle(base) => self.recycle.iter_mut().find(|block| block.base() == base),
+        };
+        let block = block.ok_or(AllocError::BadRequest)?;
+        let space = block.inner_alloc_aligned(alloc_size, align, payload_offset);
+
+        self.hole_scans += block.take_hole_scans();
+        self.hole_moves += block.take_left_hole() as usize;
+
+        if let BlockTarget::Recycle(_) = target {
+            self.scattered_allocs += space.is_some() as usize;
+        }
+
+        space.ok_or(AllocError::OOM)
     }
 
-    struct MediumTestObj {
-        data: [u8; 256],
+    fn large_alloc(
+        &mut self,
+        alloc_size: usize,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        // blocks are aligned to their size, so only the payload offset needs
+        // shifting to meet `align`
+        let shift = (align - payload_offset % align) % align;
+
+        #[cfg(feature = "testing")]
+        self.inject_failure()?;
+
+        let block = Block::new_rounded_in(max(alloc_size + shift, align), self.source.clone())?;
+        let header = unsafe { block.as_ptr().add(shift) };
+
+        self.large.push(LargeObject { _block: block, header });
+
+        Ok(header)
     }
 
-    fn alloc_size<T>() -> usize {
-        let header_size = size_of::<TestHeader>();
-        let header_alloc_size = add_alignment_padding(header_size);
-        let object_size = size_of::<T>();
-        let total_size = header_alloc_size + object_size;
-        let alloc_size = add_alignment_padding(total_size);
-        /*
-            let alignment = size_of::<usize>(); 
-            println!("ALIGNMENT: {}", alignment);       // 8
-            println!("HEADER_SIZE: {}", header_size);   // 8
-            println!("HEADER_ALLOC_SIZE: {}", header_alloc_size);   // 8
-            println!("OBJECT_SIZE: {}", object_size);   // 4
-            println!("TOTAL_SIZE:  {}",  total_size);   // 12
-            println!("ALLOC_SIZE:  {}",  alloc_size);   // 16
-        */
-        alloc_size
+    fn get_free_block(&mut self) -> Result<BumpBlock, AllocError> {
+        #[cfg(feature = "testing")]
+        self.inject_failure()?;
+
+        let pooled = match self.free.pop() {
+            Some(block) => Some(block),
+            None => self.take_pooled_block(),
+        };
+
+        let mut block = match pooled {
+            Some(block) => block,
+            None => BumpBlock::new_in(self.source.clone())?,
+        };
+
+        block.set_hole_strategy(self.hole_strategy);
+        block.set_line_size(self.line_size);
+
+        if self.side_metadata {
+            block.use_side_metadata();
+        }
+
+        block.set_mark_generation(self.mark_generation);
+
+        Ok(block)
     }
 
-    impl AllocObject<TestTypeId> for MediumTestObj {
-        const TYPE_ID: TestTypeId = TestTypeId::Medium;
+    // fails once, when the countdown set by set_fail_after runs out
+    #[cfg(feature = "testing")]
+    fn inject_failure(&mut self) -> Result<(), AllocError> {
+        match self.fail_after {
+            Some(0) => {
+                self.fail_after = None;
+
+                Err(AllocError::OOM)
+            }
+
+            Some(ref mut remaining) => {
+                *remaining -= 1;
+
+                Ok(())
+            }
+
+            None => Ok(()),
+        }
     }
 
-    impl AllocObject<TestTypeId> for SmallTestObj {
-        const TYPE_ID: TestTypeId = TestTypeId::Small;
+    // only locks once the local free list has run dry
+    #[cfg(feature = "std")]
+    fn take_pooled_block(&mut self) -> Option<BumpBlock> {
+        let pool = self.pool.as_ref()?;
+        let block = pool.lock().unwrap().pop()?;
+
+        Some(BumpBlock::from_block(block))
     }
 
-    #[derive(PartialEq, Copy, Clone)]
-    enum TestTypeId {
-        Small,
-        Medium,
-        Large,
-        Array,
+    #[cfg(not(feature = "std"))]
+    fn take_pooled_block(&mut self) -> Option<BumpBlock> {
+        None
     }
 
-    impl AllocTypeId for TestTypeId {}
+    // hands every free block back to the shared pool, if there is one
+    #[cfg(feature = "std")]
+    fn release_free_blocks(&mut self) {
+        if let Some(ref pool) = self.pool {
+            let mut pool = pool.lock().unwrap();
 
-    struct TestHeader {
-        mark: Mark,
-        type_id: TestTypeId,
-        size: u32,
-        size_class: SizeClass
+            pool.extend(self.free.drain(..).map(BumpBlock::into_block));
+        }
     }
 
-    impl AllocHeader for TestHeader {
-        type TypeId = TestTypeId;
+    #[cfg(not(feature = "std"))]
+    fn release_free_blocks(&mut self) {}
 
-        fn new<O: AllocObject<Self::TypeId>>(size: u32, size_class: SizeClass, mark: Mark) -> Self {
-            TestHeader {
-                type_id: O::TYPE_ID,
-                mark,
-                size,
-                size_class
+    fn sweep(&mut self) {
+        let mut retired = take(&mut self.used);
+
+        retired.append(&mut self.recycle.drain());
+
+        for mut block in retired {
+            if !block.is_marked() || !block.has_marked_lines() {
+                block.reset();
+                self.free.push(block);
+                continue;
             }
-        }
+
+            block.survive();
+
+            if block.has_hole() {
+                block.rewind();
+                self.recycle.push(block);
+            } else {
+                self.used.push(block);
+            }
+        }
+
+        // an empty overflow block would otherwise be held until it fills
+        if let Some(overflow) = self.overflow.as_ref() {
+            if !overflow.is_marked() || !overflow.has_marked_lines() {
+                let mut block = self.overflow.take().unwrap();
+
+                block.reset();
+                self.free.push(block);
+            }
+        }
+
+        self.release_free_blocks();
+        self.bytes_allocated = 0;
+    }
+
+    fn reset(&mut self) {
+        let mut retired = take(&mut self.used);
+
+        retired.append(&mut self.recycle.drain());
+        retired.extend(self.head.take());
+        retired.extend(self.overflow.take());
+
+        for mut block in retired {
+            block.reset();
+            self.free.push(block);
+        }
+
+        self.large.clear();
+        self.release_free_blocks();
+        self.bytes_allocated = 0;
+    }
+
+    fn checkpoint(&self) -> SpaceCheckpoint {
+        let bounds = |block: &BumpBlock| (block.base(), block.cursor(), block.limit());
+
+        SpaceCheckpoint {
+            head: self.head.as_ref().map(bounds),
+            overflow: self.overflow.as_ref().map(bounds),
+            recycle: self.recycle.iter().map(|block| block.base()).collect(),
+            used: self.used.len(),
+            large: self.large.len(),
+            bytes_allocated: self.bytes_allocated,
+            hole_moves: self.hole_moves,
+            scattered_allocs: self.scattered_allocs,
+        }
+    }
+
+    // rewinding bounds only drops objects from the holes being bumped at the
+    // checkpoint. one that a bump allocator moved on from, or that went into
+    // some other block, would be left behind.
+    fn can_reset_to(&self, checkpoint: &SpaceCheckpoint) -> bool {
+        self.hole_moves == checkpoint.hole_moves && self.scattered_allocs == checkpoint.scattered_allocs
+    }
+
+    // blocks that have been bump allocators since the checkpoint either get
+    // their old bounds back or are freed
+    fn reset_to(&mut self, checkpoint: &SpaceCheckpoint) {
+        let mut touched = self.used.split_off(checkpoint.used);
+
+        touched.extend(self.head.take());
+        touched.extend(self.overflow.take());
+
+        for mut block in touched {
+            let base = block.base();
+
+            match (checkpoint.head, checkpoint.overflow) {
+                (Some((head, cursor, limit)), _) if head == base => {
+                    block.rewind_to(cursor, limit);
+                    self.head = Some(block);
+                }
+
+                (_, Some((overflow, cursor, limit))) if overflow == base => {
+                    block.rewind_to(cursor, limit);
+                    self.overflow = Some(block);
+                }
+
+                _ if checkpoint.recycle.contains(&base) => {
+                    block.rewind();
+                    self.recycle.push(block);
+                }
+
+                _ => {
+                    block.reset();
+                    self.free.push(block);
+                }
+            }
+        }
+
+        self.large.truncate(checkpoint.large);
+        self.release_free_blocks();
+        self.bytes_allocated = checkpoint.bytes_allocated;
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &BumpBlock> {
+        self.head
+            .iter()
+            .chain(self.overflow.iter())
+            .chain(self.recycle.iter())
+            .chain(self.used.iter())
+    }
+
+    // every object header, large ones included
+    fn headers(&self) -> impl Iterator<Item = *const u8> + '_ {
+        self.iter()
+            .flat_map(|block| block.objects())
+            .chain(self.large.iter().map(|large| large.header))
+    }
+
+    fn roles(&self) -> impl Iterator<Item = (BlockRole, &BumpBlock)> {
+        let with_role = |role| move |block| (role, block);
+
+        self.head
+            .iter()
+            .map(with_role(BlockRole::Head))
+            .chain(self.overflow.iter().map(with_role(BlockRole::Overflow)))
+            .chain(self.recycle.iter().map(with_role(BlockRole::Recycle)))
+            .chain(self.used.iter().map(with_role(BlockRole::Used)))
+            .chain(self.free.iter().map(with_role(BlockRole::Free)))
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut BumpBlock> {
+        self.head
+            .iter_mut()
+            .chain(self.overflow.iter_mut())
+            .chain(self.recycle.iter_mut())
+            .chain(self.used.iter_mut())
+    }
+
+    // `contains` only compares the masked block base, so this is a cheap
+    // scan rather than a range check per block
+    fn block_for(&mut self, ptr: *const u8) -> Option<&mut BumpBlock> {
+        self.iter_mut().find(|block| block.contains(ptr))
+    }
+
+    fn rollback(&mut self, space: *const u8, alloc_size: usize) {
+        match self.block_for(space) {
+            Some(block) => block.rollback(space, alloc_size),
+            None => self.large.retain(|large| large.header != space),
+        }
+
+        // a sweep survivor was counted before the sweep zeroed the total
+        self.bytes_allocated = self.bytes_allocated.saturating_sub(alloc_size);
+    }
+
+    // stops walking the object at `header`, so nothing finalizes it. moved
+    // large objects stay allocated until the next sweep. returns false if
+    // `header` isn't in this space.
+    fn forget(&mut self, header: *const u8) -> bool {
+        if let Some(block) = self.block_for(header) {
+            block.forget_object(header);
+
+            return true;
+        }
+
+        match self.large.iter().position(|large| large.header == header) {
+            Some(index) => {
+                let large = self.large.remove(index);
+
+                self.moved_large.push(large);
+
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    fn owns(&self, ptr: *const u8) -> bool {
+        let in_large = |large: &LargeObject| {
+            let base = large._block.as_ptr();
+
+            ptr >= base && ptr < unsafe { base.add(large._block.size()) }
+        };
+
+        self.iter().any(|block| block.contains(ptr)) || self.large.iter().any(in_large)
+    }
+
+    fn block_index_of(&self, ptr: *const u8) -> Option<usize> {
+        self.iter().position(|block| block.contains(ptr))
+    }
+
+    // returns false if `ptr` isn't in any of these blocks
+    fn mark_span(&mut self, ptr: *const u8, size: usize) -> bool {
+        match self.block_for(ptr) {
+            Some(block) => {
+                // conservative spans can run off the end of the block
+                let size = min(size, constants::BLOCK_CAPACITY - block.offset_of(ptr));
+
+                block.mark_span(ptr, size);
+
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    // starts a new mark phase. stale bytes could alias a reused generation,
+    // so they're only cleared when the counter wraps.
+    fn advance_mark_generation(&mut self) {
+        let mark_generation = match self.mark_generation.checked_add(1) {
+            Some(mark_generation) => mark_generation,
+
+            None => {
+                for block in self.iter_mut() {
+                    block.clear_marks();
+                }
+
+                constants::MARKED
+            }
+        };
+
+        self.mark_generation = mark_generation;
+
+        for block in self.iter_mut() {
+            block.set_mark_generation(mark_generation);
+        }
+    }
+
+    fn set_hole_strategy(&mut self, hole_strategy: HoleStrategy) {
+        self.hole_strategy = hole_strategy;
+
+        if let Some(ref mut head) = self.head {
+            head.set_hole_strategy(hole_strategy);
+        }
+
+        if let Some(ref mut overflow) = self.overflow {
+            overflow.set_hole_strategy(hole_strategy);
+        }
+    }
+}
+
+// gives back a reservation whose object never got written
+struct AllocGuard<'a> {
+    blocks: &'a UnsafeCell<BlockList>,
+    space: *const u8,
+    alloc_size: usize,
+}
+
+impl Drop for AllocGuard<'_> {
+    fn drop(&mut self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.rollback(self.space, self.alloc_size);
+    }
+}
+
+type TraceFn = fn(NonNull<()>, &mut dyn FnMut(NonNull<()>));
+type DropFn = fn(NonNull<()>);
+
+fn drop_object<T>(object: NonNull<()>) {
+    unsafe { drop_in_place(object.cast::<T>().as_ptr()) };
+}
+
+fn trace_object<T: Trace>(object: NonNull<()>, mark: &mut dyn FnMut(NonNull<()>)) {
+    unsafe { object.cast::<T>().as_ref() }.trace(mark);
+}
+
+// `blocks` is the nursery. `tenured` stays empty unless something is
+// allocated with alloc_old.
+//
+// the heap is free to move: every pointer it keeps points into block memory
+// or a boxed side table, never into the heap itself or a BumpBlock, and
+// BumpBlock::meta recomputes inline metadata addresses on each call. new
+// state has to keep it that way rather than rely on Pin.
+pub struct ZapHeap<H: AllocHeader> {
+    blocks: UnsafeCell<BlockList>,
+    tenured: UnsafeCell<BlockList>,
+    tracers: UnsafeCell<TypeMap<H::TypeId, TraceFn>>,
+    layouts: UnsafeCell<TypeMap<H::TypeId, &'static [usize]>>,
+    droppers: UnsafeCell<TypeMap<H::TypeId, DropFn>>,
+    #[cfg(feature = "profiling")]
+    histogram: UnsafeCell<Option<TypeHistogram<H::TypeId>>>,
+    #[cfg(feature = "profiling")]
+    latency: UnsafeCell<LatencyStats>,
+    #[cfg(feature = "debug-alloc-sites")]
+    alloc_sites: UnsafeCell<BTreeMap<usize, &'static Location<'static>>>,
+    alloc_log: UnsafeCell<Option<Vec<AllocEvent<H::TypeId>>>>,
+    sampler: UnsafeCell<Option<Sampler<H::TypeId>>>,
+    weak_refs: UnsafeCell<BTreeMap<usize, WeakSlot>>,
+    handles: UnsafeCell<HandleTable>,
+    // (align, aligned_offset) of each object placed with more than
+    // ALLOC_ALIGN, so moving it can keep that alignment
+    alignments: UnsafeCell<BTreeMap<usize, (usize, usize)>>,
+    redzone: usize,
+    policy: UnsafeCell<Option<Box<dyn CollectionPolicy>>>,
+    _header_type: PhantomData<*const H>,
+}
+
+impl<H: AllocHeader> ZapHeap<H> {
+    // objects start straight after the padded header, and small objects
+    // have to leave room in their line for a payload
+    const HEADER_FITS: () = assert!(
+        size_of::<H>() <= constants::LINE_SIZE && align_of::<H>() <= constants::ALLOC_ALIGN,
+        "the header must fit in a line and need no more than ALLOC_ALIGN alignment"
+    );
+
+    // a heap that takes blocks from, and sweeps free blocks into, `pool`
+    #[cfg(feature = "std")]
+    pub(crate) fn with_pool(pool: BlockPool) -> ZapHeap<H> {
+        let heap = ZapHeap::new();
+
+        unsafe {
+            (*heap.blocks.get()).pool = Some(pool.clone());
+            (*heap.tenured.get()).pool = Some(pool);
+        }
+
+        heap
+    }
+
+    // every block this heap allocates itself comes from `source`
+    pub fn with_source(source: Arc<dyn BlockSource>) -> ZapHeap<H> {
+        let heap = ZapHeap::empty();
+
+        unsafe {
+            (*heap.blocks.get()).source = Some(source.clone());
+            (*heap.tenured.get()).source = Some(source);
+        }
+
+        heap
+    }
+
+    // every object gets a poisoned redzone after it for check_redzones to
+    // inspect, at the cost of REDZONE_SIZE bytes each
+    #[cfg(any(feature = "std", test))]
+    pub fn with_redzones() -> ZapHeap<H> {
+        let mut heap = ZapHeap::new();
+
+        heap.redzone = constants::REDZONE_SIZE;
+
+        heap
+    }
+
+    // lines of `line_size` bytes rather than LINE_SIZE. it has to be a power
+    // of two, no smaller than a header and at most half a block.
+    #[cfg(any(feature = "std", test))]
+    pub fn with_line_size(line_size: usize) -> Result<ZapHeap<H>, AllocError> {
+        if !line_size.is_power_of_two() || line_size < max(size_of::<H>(), constants::ALLOC_ALIGN) || line_size > constants::BLOCK_SIZE / 2 {
+            return Err(AllocError::BadRequest);
+        }
+
+        let heap = ZapHeap::new();
+
+        unsafe {
+            (*heap.blocks.get()).line_size = line_size;
+            (*heap.tenured.get()).line_size = line_size;
+        }
+
+        Ok(heap)
+    }
+
+    // fills the free list up front so the first `blocks` block fetches
+    // don't reach the system allocator
+    #[cfg(any(feature = "std", test))]
+    pub fn with_prealloc(blocks: usize) -> Result<ZapHeap<H>, AllocError> {
+        let heap = ZapHeap::new();
+        let free = unsafe { &mut (*heap.blocks.get()).free };
+
+        free.reserve(blocks);
+
+        for _ in 0..blocks {
+            free.push(BumpBlock::new()?);
+        }
+
+        Ok(heap)
+    }
+
+    /// A header bigger than a line is a compile error:
+    ///
+    /// ```compile_fail
+    /// use zapalloc::{AllocHeader, AllocObject, AllocTypeId, ArraySize, Mark, SizeClass, ZapHeap};
+    ///
+    /// #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+    /// struct Id;
+    ///
+    /// impl AllocTypeId for Id {}
+    ///
+    /// struct HugeHeader {
+    ///     _bytes: [u8; 256],
+    /// }
+    ///
+    /// impl AllocHeader for HugeHeader {
+    ///     type TypeId = Id;
+    ///
+    ///     fn new<O: AllocObject<Id>>(_: u32, _: SizeClass, _: Mark) -> Self { unimplemented!() }
+    ///     fn new_array(_: ArraySize, _: SizeClass, _: Mark) -> Self { unimplemented!() }
+    ///     fn new_bytes(_: Id, _: ArraySize, _: SizeClass, _: Mark) -> Self { unimplemented!() }
+    ///     fn mark(&mut self) {}
+    ///     fn unmark(&mut self) {}
+    ///     fn mark_freed(&mut self) {}
+    ///     fn mark_forwarded(&mut self) {}
+    ///     fn reset_mark(&mut self) {}
+    ///     fn is_marked(&self) -> bool { false }
+    ///     fn mark_state(&self) -> Mark { Mark::Allocated }
+    ///     fn pin(&mut self) {}
+    ///     fn unpin(&mut self) {}
+    ///     fn is_pinned(&self) -> bool { false }
+    ///     fn size_class(&self) -> SizeClass { SizeClass::Small }
+    ///     fn size(&self) -> u32 { 0 }
+    ///     fn type_id(&self) -> Id { Id }
+    /// }
+    ///
+    /// let heap = ZapHeap::<HugeHeader>::new();
+    /// ```
+    #[cfg(any(feature = "std", test))]
+    pub fn new() -> ZapHeap<H> {
+        ZapHeap::empty()
+    }
+
+    // blocks come from the global allocator unless a source is set. without
+    // std, with_source is the only way in outside of tests.
+    fn empty() -> ZapHeap<H> {
+        let () = Self::HEADER_FITS;
+
+        ZapHeap {
+            blocks: UnsafeCell::new(BlockList::new()),
+            tenured: UnsafeCell::new(BlockList::new()),
+            tracers: UnsafeCell::new(TypeMap::new()),
+            layouts: UnsafeCell::new(TypeMap::new()),
+            droppers: UnsafeCell::new(TypeMap::new()),
+            #[cfg(feature = "profiling")]
+            histogram: UnsafeCell::new(None),
+            #[cfg(feature = "profiling")]
+            latency: UnsafeCell::new(LatencyStats::default()),
+            #[cfg(feature = "debug-alloc-sites")]
+            alloc_sites: UnsafeCell::new(BTreeMap::new()),
+            alloc_log: UnsafeCell::new(None),
+            sampler: UnsafeCell::new(None),
+            weak_refs: UnsafeCell::new(BTreeMap::new()),
+            handles: UnsafeCell::new(HandleTable::new()),
+            alignments: UnsafeCell::new(BTreeMap::new()),
+            redzone: 0,
+            policy: UnsafeCell::new(None),
+            _header_type: PhantomData,
+        }
+    }
+
+    // redoes `log` in a new heap, which keeps logging. payloads are left
+    // uninitialized, so this is for reproducing layouts, not contents.
+    #[cfg(any(feature = "std", test))]
+    pub fn replay(log: &[AllocEvent<H::TypeId>]) -> Result<ZapHeap<H>, AllocError> {
+        let heap = ZapHeap::new();
+
+        heap.record_allocs();
+
+        for event in log {
+            let space = match event.op {
+                AllocOp::Young => &heap.blocks,
+                AllocOp::Old => &heap.tenured,
+            };
+
+            heap.alloc_space_aligned_at(space, None, event.size, event.align, event.aligned_offset, |size_class| {
+                H::new_bytes(event.type_id, event.size as ArraySize, size_class, Mark::Allocated)
+            })?;
+        }
+
+        Ok(heap)
+    }
+
+    // starts a fresh allocation log
+    pub fn record_allocs(&self) {
+        unsafe { *self.alloc_log.get() = Some(Vec::new()) };
+    }
+
+    pub fn alloc_log(&self) -> Vec<AllocEvent<H::TypeId>> {
+        let alloc_log = unsafe { &*self.alloc_log.get() };
+
+        alloc_log.clone().unwrap_or_default()
+    }
+
+    // keeps the last `capacity` of every `period`th allocation, replacing
+    // any earlier samples. a zero capacity stops sampling.
+    pub fn sample_allocs(&self, period: usize, capacity: usize) {
+        let sampler = match capacity {
+            0 => None,
+            _ => {
+                let period = max(period, 1);
+
+                Some(Sampler {
+                    period,
+                    countdown: period,
+                    capacity,
+                    samples: VecDeque::with_capacity(capacity),
+                })
+            }
+        };
+
+        unsafe { *self.sampler.get() = sampler };
+    }
+
+    // oldest first
+    pub fn samples(&self) -> Vec<AllocSample<H::TypeId>> {
+        let sampler = unsafe { &*self.sampler.get() };
+
+        match sampler {
+            Some(sampler) => sampler.samples.iter().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // prefaults every block on either free list, for warming up before
+    // latency matters
+    pub fn prefault_free(&self) {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        for block in blocks.free.iter().chain(tenured.free.iter()) {
+            block.prefault();
+        }
+    }
+
+    // a block off the nursery's or the tenured space's free list, wiped back
+    // to its defaults so any heap can take it
+    pub fn take_free_block(&self) -> Option<FreeBlock> {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let block = blocks.free.pop().or_else(|| tenured.free.pop())?;
+
+        Some(FreeBlock {
+            block: BumpBlock::from_block(block.into_block()),
+        })
+    }
+
+    // the nursery allocates from `block` before asking for new ones
+    pub fn give_block(&self, block: FreeBlock) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.free.push(block.block);
+    }
+
+    pub fn line_size(&self) -> usize {
+        unsafe { &*self.blocks.get() }.line_size
+    }
+
+    // the bytes of a block objects can use, less the line marks
+    pub const fn block_capacity() -> usize {
+        constants::BLOCK_CAPACITY
+    }
+
+    pub fn block_count(&self) -> usize {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        blocks.block_count() + tenured.block_count()
+    }
+
+    pub fn set_hole_strategy(&self, hole_strategy: HoleStrategy) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.set_hole_strategy(hole_strategy);
+    }
+
+    // only blocks handed out after this call move their marks aside
+    pub fn set_side_metadata(&self, enabled: bool) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+
+        blocks.side_metadata = enabled;
+        tenured.side_metadata = enabled;
+    }
+
+    // extra lines the mark phase marks past each small object, in case it
+    // strays over a line boundary. hole finding already skips one line after
+    // every marked line on top of these.
+    pub fn set_conservative_lines(&self, lines: usize) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+
+        blocks.conservative_lines = lines;
+        tenured.conservative_lines = lines;
+    }
+
+    pub fn set_gc_threshold(&self, bytes: usize) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.gc_threshold = Some(bytes);
+    }
+
+    // lets the next `blocks` nursery blocks or large objects be taken, then
+    // fails the one after with OOM. later allocations go back to normal.
+    #[cfg(feature = "testing")]
+    pub fn set_fail_after(&self, blocks: usize) {
+        unsafe { &mut *self.blocks.get() }.fail_after = Some(blocks);
+    }
+
+    pub fn reserve(&self, bytes: usize) -> Result<(), AllocError> {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.reserve(bytes)
+    }
+
+    // retires the nursery head, so the next small allocation takes another
+    // block
+    pub fn recycle_head(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.recycle_head();
+    }
+
+    pub fn should_collect(&self) -> bool {
+        let blocks = unsafe { &*self.blocks.get() };
+
+        match blocks.gc_threshold {
+            Some(threshold) => blocks.bytes_allocated >= threshold,
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        HeapStats {
+            bytes_allocated: blocks.bytes_allocated + tenured.bytes_allocated,
+            blocks: blocks.iter().count() + tenured.iter().count(),
+            free_blocks: blocks.free.len() + tenured.free.len(),
+            large_objects: blocks.large.len() + tenured.large.len(),
+            fragmentation: self.fragmentation(),
+            total_refills: blocks.refills + tenured.refills,
+            total_hole_scans: blocks.hole_scans + tenured.hole_scans,
+        }
+    }
+
+    // the bytes taken by every object not found dead by the last mark,
+    // headers and redzones included. unlike stats, this walks each object.
+    pub fn live_bytes_exact(&self) -> usize {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        blocks
+            .headers()
+            .chain(tenured.headers())
+            .map(|header| unsafe { &*(header as *const H) })
+            .filter(|header| header.mark_state() != Mark::Unmarked)
+            .map(|header| self.alloc_size_of(header.size() as usize))
+            .sum()
+    }
+
+    // the object address of everything live_bytes_exact counts, to diff
+    // against a later snapshot
+    pub fn live_set(&self) -> BTreeSet<usize> {
+        self.iter_entries()
+            .filter(|(header, ..)| unsafe { header.as_ref() }.mark_state() != Mark::Unmarked)
+            .map(|(_, object, ..)| object.as_ptr() as usize)
+            .collect()
+    }
+
+    // (added, removed) going from the `before` live set to `after`, lowest
+    // address first
+    pub fn diff(before: &BTreeSet<usize>, after: &BTreeSet<usize>) -> (Vec<usize>, Vec<usize>) {
+        (after.difference(before).copied().collect(), before.difference(after).copied().collect())
+    }
+
+    // (header, object, type id, size) of every object the heap still walks,
+    // dead ones the sweeper hasn't reused included, nursery first
+    pub fn iter_entries(&self) -> impl Iterator<Item = (NonNull<H>, NonNull<()>, H::TypeId, u32)> + '_ {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        blocks.headers().chain(tenured.headers()).map(|header| {
+            let header = unsafe { NonNull::new_unchecked(header as *mut H) };
+            let object = Self::get_object(header);
+            let entry = unsafe { header.as_ref() };
+
+            (header, object, entry.type_id(), entry.size())
+        })
+    }
+
+    // replaces the gc threshold as what maybe_collect goes by
+    pub fn set_policy(&self, policy: Box<dyn CollectionPolicy>) {
+        unsafe { *self.policy.get() = Some(policy) };
+    }
+
+    // runs whatever the policy asks for, or collects past the gc threshold
+    // without one. like sweep, it has to follow a mark phase. evacuation
+    // collects first, so the survivors' blocks are up for evacuating.
+    pub fn maybe_collect<F>(&self, remap: &mut F) -> Result<CollectDecision, AllocError>
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+        H::TypeId: Hash + Eq,
+    {
+        let policy = unsafe { &*self.policy.get() };
+        let decision = match policy {
+            Some(policy) => policy.should_collect(&self.stats()),
+            None if self.should_collect() => CollectDecision::Collect,
+            None => CollectDecision::Skip,
+        };
+
+        match decision {
+            CollectDecision::Skip => (),
+            CollectDecision::Collect => self.collect(),
+
+            CollectDecision::Evacuate => {
+                self.collect();
+                self.evacuate(remap)?;
+            }
+        }
+
+        Ok(decision)
+    }
+
+    // sweeping trusts the marks from the last mark phase, so it has to run
+    // before anything else is allocated. newer objects have no marks yet and
+    // would be treated as dead.
+    pub fn sweep(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+
+        #[cfg(feature = "debug-alloc-sites")]
+        self.forget_dead_sites();
+
+        self.clear_dead_weak_refs(|_| true);
+        self.forget_dead_alignments(|_| true);
+        self.forget_dead_handles(|_| true);
+        Self::sweep_space(blocks);
+        Self::sweep_space(tenured);
+    }
+
+    // weak refs to zero-sized objects never clear, there being no header to
+    // say whether they're live
+    pub fn new_weak<T>(&self, ptr: RawPtr<T>) -> WeakRef<T> {
+        if size_of::<T>() == 0 {
+            return WeakRef::new(Rc::new(Cell::new(Some(ptr.as_ptr() as *const ()))));
+        }
+
+        let weak_refs = unsafe { &mut *self.weak_refs.get() };
+        let slot = weak_refs
+            .entry(ptr.as_word())
+            .or_insert_with(|| Rc::new(Cell::new(Some(ptr.as_ptr() as *const ()))));
+
+        WeakRef::new(slot.clone())
+    }
+
+    // has to run while the dead objects' memory is still mapped. entries
+    // nothing else holds are dropped along the way.
+    fn clear_dead_weak_refs<F>(&self, in_swept_space: F)
+    where
+        F: Fn(*const u8) -> bool,
+    {
+        let weak_refs = unsafe { &mut *self.weak_refs.get() };
+
+        weak_refs.retain(|object, slot| {
+            let object = unsafe { NonNull::new_unchecked(*object as *mut ()) };
+
+            let live = unsafe { Self::get_header(object).as_ref() }.is_marked();
+
+            if !live && in_swept_space(object.as_ptr() as *const u8) {
+                slot.set(None);
+
+                return false;
+            }
+
+            Rc::strong_count(slot) > 1
+        });
+    }
+
+    // repoints the weak refs and handle to an object that moved
+    fn move_refs(&self, old: NonNull<()>, new: NonNull<()>) {
+        let weak_refs = unsafe { &mut *self.weak_refs.get() };
+        let handles = unsafe { &mut *self.handles.get() };
+
+        if let Some(slot) = weak_refs.remove(&(old.as_ptr() as usize)) {
+            slot.set(Some(new.as_ptr() as *const ()));
+            weak_refs.insert(new.as_ptr() as usize, slot);
+        }
+
+        handles.repoint(old, new);
+
+        let alignments = unsafe { &mut *self.alignments.get() };
+
+        if let Some(alignment) = alignments.remove(&(old.as_ptr() as usize)) {
+            alignments.insert(new.as_ptr() as usize, alignment);
+        }
+    }
+
+    // an entry left by an object since overwritten only over-aligns the
+    // next move, so the inline fast path doesn't have to clear it
+    fn record_alignment(&self, object: *const u8, align: usize, aligned_offset: usize) {
+        let alignments = unsafe { &mut *self.alignments.get() };
+
+        if align > constants::ALLOC_ALIGN {
+            alignments.insert(object as usize, (align, aligned_offset));
+        } else if !alignments.is_empty() {
+            alignments.remove(&(object as usize));
+        }
+    }
+
+    // the (align, aligned_offset) to move the object at `header` with
+    fn alignment_of(&self, header: *const u8) -> (usize, usize) {
+        let alignments = unsafe { &*self.alignments.get() };
+        let object = header as usize + add_alignment_padding(size_of::<H>());
+
+        alignments.get(&object).copied().unwrap_or((constants::ALLOC_ALIGN, 0))
+    }
+
+    // has to run while the dead objects' memory is still mapped. entries
+    // outside both spaces are for memory already given back.
+    fn forget_dead_alignments<F>(&self, in_swept_space: F)
+    where
+        F: Fn(*const u8) -> bool,
+    {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let alignments = unsafe { &mut *self.alignments.get() };
+
+        alignments.retain(|object, _| {
+            let object = *object as *const u8;
+
+            if !blocks.owns(object) && !tenured.owns(object) {
+                return false;
+            }
+
+            let object = unsafe { NonNull::new_unchecked(object as *mut ()) };
+
+            !in_swept_space(object.as_ptr() as *const u8) || unsafe { Self::get_header(object).as_ref() }.is_marked()
+        });
+    }
+
+    // like clear_dead_weak_refs, so an object allocated where a dead one
+    // was doesn't take over its handle
+    fn forget_dead_handles<F>(&self, in_swept_space: F)
+    where
+        F: Fn(*const u8) -> bool,
+    {
+        let handles = unsafe { &mut *self.handles.get() };
+
+        handles.forget_unless(|object| {
+            !in_swept_space(object.as_ptr() as *const u8) || unsafe { Self::get_header(object).as_ref() }.is_marked()
+        });
+    }
+
+    // alloc, returning a handle that keeps resolving to the object wherever
+    // it moves. handles aren't roots, and one outlives its object only as a
+    // dangling entry.
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub fn alloc_handle<T>(&self, object: T) -> Result<Handle, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let ptr = self.alloc(object)?;
+        let handles = unsafe { &mut *self.handles.get() };
+
+        Ok(handles.insert(ptr.as_untyped()))
+    }
+
+    pub fn resolve(&self, handle: Handle) -> NonNull<()> {
+        let handles = unsafe { &*self.handles.get() };
+
+        handles.get(handle)
+    }
+
+    fn sweep_space(blocks: &mut BlockList) {
+        blocks.moved_large.clear();
+        blocks.large.retain(|large| unsafe { &*(large.header as *const H) }.is_marked());
+        blocks.sweep();
+    }
+
+    // the (cursor, limit) of the nursery's current hole, for inline bump
+    // allocation fast paths
+    pub fn bump_bounds(&self) -> Option<(*const u8, *const u8)> {
+        let blocks = unsafe { &*self.blocks.get() };
+
+        blocks.head.as_ref().map(|head| (head.cursor(), head.limit()))
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `BumpBlock::publish_cursor`, against the bounds last
+    /// returned by `bump_bounds` with no allocation in between.
+    pub unsafe fn publish_cursor(&self, cursor: *const u8) {
+        let blocks = &mut *self.blocks.get();
+
+        if let Some(ref mut head) = blocks.head {
+            blocks.bytes_allocated += head.cursor() as usize - cursor as usize;
+            head.publish_cursor(cursor);
+        }
+    }
+
+    // 1 - marked lines / committed lines, over every block in use. only
+    // meaningful right after marking.
+    pub fn fragmentation(&self) -> f64 {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let mut committed = 0;
+        let mut occupied = 0;
+
+        for block in blocks.iter().chain(tenured.iter()) {
+            committed += block.line_count();
+            occupied += block.occupied_lines();
+        }
+
+        if committed == 0 {
+            return 0.0;
+        }
+
+        1.0 - occupied as f64 / committed as f64
+    }
+
+    // (block index, offset, size) of every free line run, per the line marks
+    // from the last mark phase
+    pub fn holes(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        blocks.iter().chain(tenured.iter()).enumerate().flat_map(|(index, block)| {
+            block.holes().into_iter().map(move |(offset, size)| (index, offset, size))
+        })
+    }
+
+    // every block of both spaces, free ones included. large objects aren't
+    // line marked, so they're left out.
+    pub fn dump_layout(&self) -> Vec<BlockLayout> {
+        self.iter_blocks(None).collect()
+    }
+
+    // dump_layout, narrowed to blocks holding an object of `class`, dead
+    // ones included. large objects have no block here either.
+    pub fn iter_blocks(&self, class: Option<SizeClass>) -> impl Iterator<Item = BlockLayout> + '_ {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let young = blocks.roles().map(|(role, block)| (AllocOp::Young, role, block));
+        let old = tenured.roles().map(|(role, block)| (AllocOp::Old, role, block));
+        let serves = move |block: &BumpBlock| match class {
+            Some(class) => block.objects().any(|object| unsafe { &*(object as *const H) }.size_class() == class),
+            None => true,
+        };
+
+        young
+            .chain(old)
+            .filter(move |(_, _, block)| serves(block))
+            .map(|(space, role, block)| BlockLayout {
+                base: block.base(),
+                space,
+                role,
+                age: block.age(),
+                marked_lines: block.metadata().iter().map(|mark| *mark == block.mark_generation()).collect(),
+            })
+    }
+
+    /// Hands `f` the line mark bytes of the block holding `ptr`, and the
+    /// byte that currently means marked, for liveness schemes of your own.
+    /// Returns `None` if `ptr` isn't in a nursery or tenured block.
+    ///
+    /// # Safety
+    ///
+    /// `f` must not use the heap. Whatever it leaves in the bytes is what the
+    /// next allocation and sweep go by.
+    pub unsafe fn with_line_metadata<R>(&self, ptr: NonNull<()>, f: impl FnOnce(&mut [u8], u8) -> R) -> Option<R> {
+        let blocks = &mut *self.blocks.get();
+        let tenured = &mut *self.tenured.get();
+        let ptr = ptr.as_ptr() as *const u8;
+        let block = match blocks.block_for(ptr) {
+            Some(block) => block,
+            None => tenured.block_for(ptr)?,
+        };
+        let mark_generation = block.mark_generation();
+
+        Some(f(block.metadata_mut(), mark_generation))
+    }
+
+    /// Marks made through the returned marker use the current mark
+    /// generation, so they count towards the next sweep like those of
+    /// `mark_from_roots`.
+    ///
+    /// # Safety
+    ///
+    /// While the marker lives, the heap must not sweep, collect, reset,
+    /// compact, evacuate or start another mark, and `with_line_metadata`
+    /// must not be called: each of those can rewrite or free the marks the
+    /// marker holds.
+    pub unsafe fn atomic_marker(&self) -> AtomicMarker<'_> {
+        let blocks = &*self.blocks.get();
+        let tenured = &*self.tenured.get();
+
+        AtomicMarker {
+            blocks: blocks.iter().chain(tenured.iter()).map(BumpBlock::atomic_marks).collect(),
+            _heap: PhantomData,
+        }
+    }
+
+    // indexes count nursery blocks first, then tenured ones
+    pub fn block_index_of(&self, ptr: NonNull<()>) -> Option<usize> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let ptr = ptr.as_ptr() as *const u8;
+
+        blocks
+            .block_index_of(ptr)
+            .or_else(|| tenured.block_index_of(ptr).map(|index| index + blocks.iter().count()))
+    }
+
+    pub fn sweep_nursery(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        #[cfg(feature = "debug-alloc-sites")]
+        self.forget_dead_sites();
+
+        self.clear_dead_weak_refs(|object| blocks.owns(object));
+        self.forget_dead_alignments(|object| blocks.owns(object));
+        self.forget_dead_handles(|object| blocks.owns(object));
+        Self::sweep_space(blocks);
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        Checkpoint {
+            nursery: blocks.checkpoint(),
+            tenured: tenured.checkpoint(),
+        }
+    }
+
+    // drops everything allocated since `checkpoint`, arena style. there
+    // must have been no collection in between. fails, changing nothing, if
+    // anything since went outside the holes bumped at the checkpoint, e.g. a
+    // medium object into a lower head hole.
+    pub fn reset_to(&self, checkpoint: Checkpoint) -> Result<(), AllocError> {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+
+        if !blocks.can_reset_to(&checkpoint.nursery) || !tenured.can_reset_to(&checkpoint.tenured) {
+            return Err(AllocError::BadRequest);
+        }
+
+        blocks.reset_to(&checkpoint.nursery);
+        tenured.reset_to(&checkpoint.tenured);
+
+        let discarded = |object: *const u8| {
+            let in_rewound = |space: &SpaceCheckpoint| {
+                space.head.iter().chain(space.overflow.iter()).any(|(_, cursor, limit)| {
+                    object >= *limit && object < *cursor
+                })
+            };
+
+            !blocks.owns(object) && !tenured.owns(object)
+                || in_rewound(&checkpoint.nursery)
+                || in_rewound(&checkpoint.tenured)
+        };
+
+        unsafe { &mut *self.weak_refs.get() }.retain(|object, slot| {
+            if discarded(*object as *const u8) {
+                slot.set(None);
+
+                return false;
+            }
+
+            true
+        });
+
+        #[cfg(feature = "debug-alloc-sites")]
+        unsafe { &mut *self.alloc_sites.get() }.retain(|object, _| !discarded(*object as *const u8));
+
+        Ok(())
+    }
+
+    // hands every large object over to the caller, who frees each with
+    // free_large once done with it
+    pub fn take_large(&self) -> Vec<(NonNull<H>, usize)> {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let mut taken = Vec::new();
+
+        for space in [blocks, tenured] {
+            for large in space.large.drain(..) {
+                let header = unsafe { NonNull::new_unchecked(large.header as *mut H) };
+                let size = header_alloc_size + unsafe { header.as_ref() }.size() as usize;
+
+                self.forget_refs(Self::get_object(header));
+                taken.push((header, size));
+                space.taken_large.push(large);
+            }
+        }
+
+        taken
+    }
+
+    pub fn free_large(&self, header: NonNull<H>) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+
+        self.forget_refs(Self::get_object(header));
+
+        let header = header.as_ptr() as *const u8;
+
+        for space in [blocks, tenured] {
+            space.large.retain(|large| large.header != header);
+            space.taken_large.retain(|large| large.header != header);
+        }
+    }
+
+    /// Gives `ptr` back without waiting for a collection, for objects the
+    /// caller owns outright. The space is reusable at once if it was the last
+    /// thing bumped, and after the next sweep otherwise. The object isn't
+    /// dropped. Freeing it again fails with `DoubleFree`, as long as nothing
+    /// has been allocated over it since.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an allocation from this heap, and neither it nor any
+    /// copy of it may be used afterwards.
+    pub unsafe fn dealloc<T>(&self, ptr: RawPtr<T>) -> Result<(), AllocError> {
+        if size_of::<T>() == 0 {
+            return Ok(());
+        }
+
+        let object = ptr.as_untyped();
+        let mut header = Self::get_header(object);
+
+        if header.as_ref().mark_state() == Mark::Freed {
+            return Err(AllocError::DoubleFree);
+        }
+
+        header.as_mut().mark_freed();
+
+        let alloc_size = self.alloc_size_of(header.as_ref().size() as usize);
+        let header = header.as_ptr() as *const u8;
+
+        // large blocks stay mapped until the sweep, so the header can still
+        // be checked
+        for space in [&self.blocks, &self.tenured] {
+            let space = &mut *space.get();
+
+            if space.block_for(header).is_some() {
+                space.rollback(header, alloc_size);
+            } else if space.forget(header) {
+                space.bytes_allocated = space.bytes_allocated.saturating_sub(alloc_size);
+            }
+        }
+
+        self.forget_refs(object);
+
+        Ok(())
+    }
+
+    // for an object leaving the heap other than by a sweep
+    fn forget_refs(&self, object: NonNull<()>) {
+        if let Some(slot) = unsafe { &mut *self.weak_refs.get() }.remove(&(object.as_ptr() as usize)) {
+            slot.set(None);
+        }
+
+        unsafe { &mut *self.handles.get() }.forget(object);
+        unsafe { &mut *self.alignments.get() }.remove(&(object.as_ptr() as usize));
+
+        #[cfg(feature = "debug-alloc-sites")]
+        unsafe { &mut *self.alloc_sites.get() }.remove(&(object.as_ptr() as usize));
+    }
+
+    // frees every nursery block, e.g. once a minor collection has copied
+    // the survivors out
+    pub fn reset_nursery(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        #[cfg(feature = "debug-alloc-sites")]
+        unsafe { &mut *self.alloc_sites.get() }.retain(|object, _| !blocks.owns(*object as *const u8));
+
+        unsafe { &mut *self.weak_refs.get() }.retain(|object, slot| {
+            let owned = blocks.owns(*object as *const u8);
+
+            if owned {
+                slot.set(None);
+            }
+
+            !owned
+        });
+
+        blocks.reset();
+    }
+
+    fn find_aligned_space(
+        &self,
+        alloc_size: usize,
+        size_class: SizeClass,
+        align: usize,
+        payload_offset: usize,
+    ) -> Result<*const u8, AllocError> {
+        let blocks = unsafe { &mut *self.blocks.get() };
+
+        blocks.find_aligned_space(alloc_size, size_class, align, payload_offset)
+    }
+}
+
+impl<H: AllocHeader> ZapHeap<H> {
+    // alloc_array without the zeroing. the bytes may hold anything, stale
+    // objects included, so each must be written before it's read as a u8.
+    pub fn alloc_array_uninit(&self, size_bytes: ArraySize) -> Result<RawPtr<MaybeUninit<u8>>, AllocError> {
+        let array_space = self.alloc_array_space(size_bytes, constants::ALLOC_ALIGN)?;
+
+        Ok(RawPtr::new(array_space as *const MaybeUninit<u8>))
+    }
+
+    // alloc, along with the bytes reserved for the object once the allocation
+    // is padded out. only `size_of::<T>()` of them hold the object.
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub fn alloc_usable<T>(&self, object: T) -> Result<(RawPtr<T>, usize), AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let ptr = self.alloc(object)?;
+
+        Ok((ptr, self.usable_size(size_of::<T>())))
+    }
+
+    // alloc_array, along with how far past `size_bytes` the array can be
+    // written. the header still records `size_bytes`.
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub fn alloc_array_usable(&self, size_bytes: ArraySize) -> Result<(RawPtr<u8>, usize), AllocError> {
+        let ptr = self.alloc_array(size_bytes)?;
+
+        Ok((ptr, self.usable_size(size_bytes as usize)))
+    }
+
+    fn usable_size(&self, object_size: usize) -> usize {
+        if object_size == 0 {
+            return 0;
+        }
+
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+
+        self.alloc_size_of(object_size) - header_alloc_size - self.redzone
+    }
+
+    // the bytes an object of `object_size` takes up, its header and any
+    // redzone included
+    fn alloc_size_of(&self, object_size: usize) -> usize {
+        add_alignment_padding(add_alignment_padding(size_of::<H>()) + object_size + self.redzone)
+    }
+
+    unsafe fn poison_redzone(&self, header: *const u8) {
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let object_end = header.add(header_alloc_size + (*(header as *const H)).size() as usize);
+
+        (object_end as *mut u8).write_bytes(constants::REDZONE_POISON, self.redzone);
+    }
+
+    // the first object, in heap order, written past its end. objects
+    // without a redzone always pass.
+    pub fn check_redzones(&self) -> Result<(), NonNull<H>> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+
+        for header in blocks.headers().chain(tenured.headers()) {
+            let size = unsafe { &*(header as *const H) }.size() as usize;
+            let redzone = unsafe { from_raw_parts(header.add(header_alloc_size + size), self.redzone) };
+
+            if redzone.iter().any(|byte| *byte != constants::REDZONE_POISON) {
+                return Err(unsafe { NonNull::new_unchecked(header as *mut H) });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn alloc_slice<T: Copy>(&self, len: usize, init: T) -> Result<RawPtr<T>, AllocError> {
+        let size_bytes = len
+            .checked_mul(size_of::<T>())
+            .and_then(|size_bytes| ArraySize::try_from(size_bytes).ok())
+            .ok_or(AllocError::BadRequest)?;
+        let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+        let array_space = self.alloc_array_space(size_bytes, align)? as *mut T;
+
+        unsafe {
+            for i in 0..len {
+                write(array_space.add(i), init);
+            }
+        }
+
+        Ok(RawPtr::new(array_space))
+    }
+
+    // `count` uninitialized `T`s in one array allocation. an empty slice, or
+    // one of zero-sized `T`s, takes no space at all.
+    pub fn alloc_array_of<T>(&self, count: usize) -> Result<RawPtr<[MaybeUninit<T>]>, AllocError> {
+        let size_bytes = count
+            .checked_mul(size_of::<T>())
+            .and_then(|size_bytes| ArraySize::try_from(size_bytes).ok())
+            .ok_or(AllocError::BadRequest)?;
+
+        let array_space = match size_bytes {
+            0 => NonNull::<MaybeUninit<T>>::dangling().as_ptr() as *const MaybeUninit<T>,
+            _ => {
+                let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+
+                self.alloc_array_space(size_bytes, align)? as *const MaybeUninit<T>
+            }
+        };
+
+        Ok(RawPtr::new(slice_from_raw_parts(array_space, count)))
+    }
+
+    /// # Safety
+    ///
+    /// The element count comes from the header, so `ptr` must be the start
+    /// of a live array allocation of `T`s from this heap.
+    pub unsafe fn array_ptr<T>(&self, ptr: RawPtr<T>) -> ArrayPtr<T> {
+        let header = unsafe { Self::get_header(ptr.as_untyped()).as_ref() };
+        let len = (header.size() as usize).checked_div(size_of::<T>()).unwrap_or(0);
+
+        ArrayPtr::new(ptr, len)
+    }
+
+    pub fn alloc_bytes(
+        &self,
+        size: usize,
+        align: usize,
+        type_id: H::TypeId,
+    ) -> Result<RawPtr<u8>, AllocError> {
+        let space = self.alloc_space(size, align, |size_class| {
+            H::new_bytes(type_id, size as ArraySize, size_class, Mark::Allocated)
+        })?;
+
+        Ok(RawPtr::new(space))
+    }
+
+    // a zeroed array in a block of its own, its payload aligned to `align`,
+    // e.g. for page aligned buffers. the header sits right below the
+    // payload, so it starts that far into the block rather than at its base.
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub fn alloc_large_aligned(&self, size_bytes: ArraySize, align: usize) -> Result<RawPtr<u8>, AllocError> {
+        if (size_bytes as usize) < constants::LARGE_OBJECT_MIN {
+            return Err(AllocError::BadRequest);
+        }
+
+        let array_space = self.alloc_array_space(size_bytes, align)?;
+
+        unsafe { (array_space as *mut u8).write_bytes(0, size_bytes as usize) };
+
+        #[cfg(feature = "debug-alloc-sites")]
+        self.record_site(unsafe { NonNull::new_unchecked(array_space as *mut ()) });
+
+        Ok(RawPtr::new(array_space))
+    }
+
+    // bytes with no header in front, aligned to `align`. nothing walks or
+    // marks them, so the next sweep takes the space back, and get_header
+    // must never be called on the result.
+    pub fn alloc_headerless(&self, size: usize, align: usize) -> Result<RawPtr<u8>, AllocError> {
+        if size > constants::MAX_ALLOC_SIZE || !align.is_power_of_two() {
+            return Err(AllocError::BadRequest);
+        }
+
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let align = max(align, constants::ALLOC_ALIGN);
+        let alloc_size = add_alignment_padding(max(size, 1));
+        let size_class = SizeClass::get_for_aligned_size(alloc_size, align)?;
+        let space = blocks.find_aligned_space(alloc_size, size_class, align, 0)?;
+
+        blocks.forget(space);
+        blocks.bytes_allocated += alloc_size;
+
+        Ok(RawPtr::new(space))
+    }
+
+    // the header size covers `T` and the trailer, which starts right after
+    // `T`, at `offset(1)` from the returned pointer
+    pub fn alloc_with_trailer<T>(&self, object: T, trailer_bytes: usize) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let object_size = size_of::<T>()
+            .checked_add(trailer_bytes)
+            .ok_or(AllocError::BadRequest)?;
+        let object_space = self.alloc_space(object_size, align_of::<T>(), |size_class| {
+            H::new::<T>(object_size as ArraySize, size_class, Mark::Allocated)
+        })? as *mut T;
+
+        unsafe {
+            write(object_space, object);
+            object_space.add(1).cast::<u8>().write_bytes(0, trailer_bytes);
+        }
+
+        Ok(RawPtr::new(object_space))
+    }
+
+    pub fn alloc_or_abort<T>(&self, object: T) -> RawPtr<T>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        match self.alloc(object) {
+            Ok(ptr) => ptr,
+            Err(error) => Self::abort_alloc(size_of::<T>(), error),
+        }
+    }
+
+    pub fn alloc_array_or_abort(&self, size_bytes: ArraySize) -> RawPtr<u8> {
+        match self.alloc_array(size_bytes) {
+            Ok(ptr) => ptr,
+            Err(error) => Self::abort_alloc(size_bytes as usize, error),
+        }
+    }
+
+    fn abort_alloc(object_size: usize, error: AllocError) -> ! {
+        let alloc_size = add_alignment_padding(size_of::<H>()).saturating_add(object_size);
+
+        match SizeClass::get_for_size(alloc_size) {
+            Ok(size_class) => panic!("{:?} allocating {} bytes ({:?})", error, object_size, size_class),
+            Err(_) => panic!("{:?} allocating {} bytes (too big for any size class)", error, object_size),
+        }
+    }
+
+    fn alloc_array_space(&self, size_bytes: ArraySize, align: usize) -> Result<*const u8, AllocError> {
+        self.alloc_space(size_bytes as usize, align, |size_class| {
+            H::new_array(size_bytes, size_class, Mark::Allocated)
+        })
+    }
+
+    fn alloc_space<F>(&self, object_size: usize, align: usize, header: F) -> Result<*const u8, AllocError>
+    where
+        F: FnOnce(SizeClass) -> H,
+    {
+        self.alloc_space_in(&self.blocks, object_size, align, header)
+    }
+
+    fn alloc_space_in<F>(
+        &self,
+        space: &UnsafeCell<BlockList>,
+        object_size: usize,
+        align: usize,
+        header: F,
+    ) -> Result<*const u8, AllocError>
+    where
+        F: FnOnce(SizeClass) -> H,
+    {
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+
+        self.alloc_space_aligned_at(space, None, object_size, align, header_alloc_size, header)
+    }
+
+    // `align` applies `aligned_offset` bytes past the header, so passing the
+    // padded header size aligns the payload and passing 0 aligns the header
+    fn alloc_space_aligned_at<F>(
+        &self,
+        space: &UnsafeCell<BlockList>,
+        near: Option<*const u8>,
+        object_size: usize,
+        align: usize,
+        aligned_offset: usize,
+        header: F,
+    ) -> Result<*const u8, AllocError>
+    where
+        F: FnOnce(SizeClass) -> H,
+    {
+        let blocks = unsafe { &mut *space.get() };
+
+        if object_size > constants::MAX_ALLOC_SIZE || !align.is_power_of_two() {
+            return Err(AllocError::BadRequest);
+        }
+
+        let align = max(align, constants::ALLOC_ALIGN);
+        let alloc_size = self.alloc_size_of(object_size);
+        let size_class = SizeClass::get_for_aligned_size(alloc_size, align)?;
+        #[cfg(feature = "profiling")]
+        let (started, taken) = (Instant::now(), blocks.blocks_taken());
+        let space = match near {
+            Some(near) => blocks.find_space_near(near, alloc_size, size_class, align, aligned_offset)?,
+            None => blocks.find_aligned_space(alloc_size, size_class, align, aligned_offset)?,
+        };
+
+        #[cfg(feature = "profiling")]
+        unsafe { &mut *self.latency.get() }.record(blocks.blocks_taken() != taken, started.elapsed().as_nanos() as u64);
+
+        Ok(self.commit_space_in_class(blocks, space, object_size, alloc_size, size_class, align, aligned_offset, header))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn commit_space_in_class<F>(
+        &self,
+        blocks: &mut BlockList,
+        space: *const u8,
+        object_size: usize,
+        alloc_size: usize,
+        size_class: SizeClass,
+        align: usize,
+        aligned_offset: usize,
+        header: F,
+    ) -> *const u8
+    where
+        F: FnOnce(SizeClass) -> H,
+    {
+        let header = header(size_class);
+        let event = AllocEvent {
+            op: if core::ptr::eq(blocks, self.tenured.get()) { AllocOp::Old } else { AllocOp::Young },
+            size: object_size,
+            align,
+            aligned_offset,
+            type_id: header.type_id(),
+        };
+
+        self.commit_space(blocks, space, alloc_size, event, header)
+    }
+
+    // bookkeeping for space `blocks` just handed out, returning the payload
+    fn commit_space(
+        &self,
+        blocks: &mut BlockList,
+        space: *const u8,
+        alloc_size: usize,
+        event: AllocEvent<H::TypeId>,
+        header: H,
+    ) -> *const u8 {
+        blocks.bytes_allocated += alloc_size;
+
+        if let Some(sampler) = unsafe { &mut *self.sampler.get() } {
+            sampler.record(AllocSample {
+                size: event.size,
+                type_id: event.type_id,
+            });
+        }
+
+        if let Some(alloc_log) = unsafe { &mut *self.alloc_log.get() } {
+            alloc_log.push(event);
+        }
+
+        unsafe {
+            write(space as *mut H, header);
+            self.poison_redzone(space);
+
+            #[cfg(feature = "profiling")]
+            self.record_alloc((*(space as *const H)).type_id());
+
+            let object = space.add(add_alignment_padding(size_of::<H>()));
+
+            self.record_alignment(object, event.align, event.aligned_offset);
+
+            object
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    fn record_alloc(&self, type_id: H::TypeId) {
+        if let Some(histogram) = unsafe { &mut *self.histogram.get() } {
+            (histogram.count)(&mut histogram.counts, type_id);
+        }
+    }
+
+    #[cfg(feature = "debug-alloc-sites")]
+    #[track_caller]
+    fn record_site(&self, object: NonNull<()>) {
+        let alloc_sites = unsafe { &mut *self.alloc_sites.get() };
+
+        alloc_sites.insert(object.as_ptr() as usize, Location::caller());
+    }
+
+    // has to run while the dead objects' memory is still mapped
+    #[cfg(feature = "debug-alloc-sites")]
+    fn forget_dead_sites(&self) {
+        let alloc_sites = unsafe { &mut *self.alloc_sites.get() };
+
+        alloc_sites.retain(|object, _| {
+            let object = unsafe { NonNull::new_unchecked(*object as *mut ()) };
+
+            unsafe { Self::get_header(object).as_ref() }.is_marked()
+        });
+    }
+
+    #[cfg(feature = "debug-alloc-sites")]
+    pub fn alloc_site(&self, ptr: NonNull<()>) -> Option<&Location<'static>> {
+        let alloc_sites = unsafe { &*self.alloc_sites.get() };
+
+        alloc_sites.get(&(ptr.as_ptr() as usize)).copied()
+    }
+
+    // starts counting allocations by type, from zero
+    #[cfg(feature = "profiling")]
+    pub fn record_type_histogram(&self)
+    where
+        H::TypeId: Hash + Eq,
+    {
+        let histogram = TypeHistogram {
+            counts: HashMap::new(),
+            count: |counts, type_id| *counts.entry(type_id).or_insert(0) += 1,
+        };
+
+        unsafe { *self.histogram.get() = Some(histogram) };
+    }
+
+    #[cfg(feature = "profiling")]
+    pub fn type_histogram(&self) -> HashMap<H::TypeId, u64>
+    where
+        H::TypeId: Hash + Eq,
+    {
+        let histogram = unsafe { &*self.histogram.get() };
+
+        histogram.as_ref().map(|histogram| histogram.counts.clone()).unwrap_or_default()
+    }
+
+    #[cfg(feature = "profiling")]
+    pub fn latency_stats(&self) -> LatencyStats {
+        let latency = unsafe { &*self.latency.get() };
+
+        latency.clone()
+    }
+
+    // the header starts a line, so the object's line marks are exact
+    pub fn alloc_line_aligned<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        if size_of::<T>() == 0 {
+            return self.alloc(object);
+        }
+
+        if align_of::<T>() > constants::ALLOC_ALIGN {
+            return Err(AllocError::BadRequest);
+        }
+
+        let object_size = size_of::<T>();
+        let header = |size_class| H::new_typed::<T>(size_class, Mark::Allocated);
+        let object_space =
+            self.alloc_space_aligned_at(&self.blocks, None, object_size, self.line_size(), 0, header)?;
+
+        unsafe {
+            write(object_space as *mut T, object);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    // puts `object` in the same block as `hint` when that block has room
+    pub fn alloc_near<T>(&self, object: T, hint: NonNull<()>) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        if size_of::<T>() == 0 {
+            return self.alloc(object);
+        }
+
+        let near = hint.as_ptr() as *const u8;
+        let tenured = unsafe { &*self.tenured.get() };
+        let space = match tenured.block_index_of(near) {
+            Some(_) => &self.tenured,
+            None => &self.blocks,
+        };
+        let object_size = size_of::<T>();
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let header = |size_class| H::new_typed::<T>(size_class, Mark::Allocated);
+        let object_space =
+            self.alloc_space_aligned_at(space, Some(near), object_size, align_of::<T>(), header_alloc_size, header)?;
+
+        unsafe {
+            write(object_space as *mut T, object);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    // places `object` in one nursery block, skipping the usual placement
+    // policy. fails if that block is missing or has no hole big enough.
+    pub fn alloc_into<T>(&self, object: T, target: BlockTarget) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let object_size = size_of::<T>();
+
+        if object_size == 0 || object_size > constants::MAX_ALLOC_SIZE {
+            return Err(AllocError::BadRequest);
+        }
+
+        let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+        let alloc_size = self.alloc_size_of(object_size);
+        let size_class = SizeClass::get_for_aligned_size(alloc_size, align)?;
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let space = blocks.find_space_in(target, alloc_size, size_class, align, header_alloc_size)?;
+        let header = |size_class| H::new_typed::<T>(size_class, Mark::Allocated);
+        let object_space =
+            self.commit_space_in_class(blocks, space, object_size, alloc_size, size_class, align, header_alloc_size, header);
+
+        unsafe {
+            write(object_space as *mut T, object);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    // `count` clones of `value`, each with its own header, sized up once
+    pub fn alloc_batch<T>(&self, value: T, count: usize) -> Result<Vec<RawPtr<T>>, AllocError>
+    where
+        T: AllocObject<H::TypeId> + Clone,
+    {
+        // grown as objects land, so a count too big for the heap fails with
+        // an AllocError rather than in a huge up front reservation
+        let mut objects = Vec::new();
+
+        if size_of::<T>() == 0 {
+            for _ in 0..count {
+                objects.push(self.alloc(value.clone())?);
+            }
+
+            return Ok(objects);
+        }
+
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let object_size = size_of::<T>();
+        let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let alloc_size = self.alloc_size_of(object_size);
+        let size_class = SizeClass::get_for_aligned_size(alloc_size, align)?;
+        let event = AllocEvent {
+            op: AllocOp::Young,
+            size: object_size,
+            align,
+            aligned_offset: header_alloc_size,
+            type_id: T::TYPE_ID,
+        };
+
+        if alloc_size.checked_mul(count).is_none() {
+            return Err(AllocError::BadRequest);
+        }
+
+        for _ in 0..count {
+            // cloned first so a panicking clone leaves nothing reserved
+            let object = value.clone();
+            let space = blocks.find_aligned_space(alloc_size, size_class, align, header_alloc_size)?;
+            let header = H::new_typed::<T>(size_class, Mark::Allocated);
+            let object_space = self.commit_space(blocks, space, alloc_size, event, header);
+
+            unsafe {
+                write(object_space as *mut T, object);
+
+                objects.push(RawPtr::new(object_space as *const T));
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Promotes the object, copying it header and all into tenured space.
+    /// Pinned objects can't move. The old copy is left for the sweeper;
+    /// callers update their own references.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point at a live `T` allocated from this heap, and the old
+    /// copy mustn't be used once the object has moved.
+    pub unsafe fn relocate<T>(&self, ptr: RawPtr<T>) -> Result<RawPtr<T>, AllocError> {
+        if size_of::<T>() == 0 {
+            return Ok(ptr);
+        }
+
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let old = ptr.as_untyped();
+        let header = unsafe { read(Self::get_header(old).as_ptr()) };
+
+        if header.is_pinned() {
+            return Err(AllocError::BadRequest);
+        }
+
+        let object_size = header.size() as usize;
+        let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let alloc_size = self.alloc_size_of(object_size);
+        let space = tenured.find_aligned_space(alloc_size, header.size_class(), align, header_alloc_size)?;
+        let event = AllocEvent {
+            op: AllocOp::Old,
+            size: object_size,
+            align,
+            aligned_offset: header_alloc_size,
+            type_id: header.type_id(),
+        };
+        let object_space = self.commit_space(tenured, space, alloc_size, event, header);
+
+        unsafe {
+            copy_nonoverlapping(old.as_ptr() as *const u8, object_space as *mut u8, object_size);
+
+            let new = NonNull::new_unchecked(object_space as *mut ());
+            let old_header = Self::get_header(old).as_ptr() as *const u8;
+
+            if !blocks.forget(old_header) {
+                tenured.forget(old_header);
+            }
+
+            self.move_refs(old, new);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    /// Moves the array into a fresh one of `size_bytes`, zeroing whatever it
+    /// grew by. The new size is classified on its own, so an array that
+    /// grows past medium ends up in large object space. The old array is left
+    /// for the sweeper, as with `relocate`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the start of a live array allocated from this heap, and
+    /// mustn't be used afterwards.
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub unsafe fn realloc_array(&self, ptr: RawPtr<u8>, size_bytes: ArraySize) -> Result<RawPtr<u8>, AllocError> {
+        let old = ptr.as_untyped();
+        let old_size = unsafe { Self::get_header(old).as_ref() }.size();
+        let new = self.alloc_array(size_bytes)?;
+
+        unsafe {
+            copy_nonoverlapping(old.as_ptr() as *const u8, new.as_ptr() as *mut u8, min(old_size, size_bytes) as usize);
+
+            let old_header = Self::get_header(old).as_ptr() as *const u8;
+
+            if !(*self.blocks.get()).forget(old_header) {
+                (*self.tenured.get()).forget(old_header);
+            }
+
+            self.move_refs(old, new.as_untyped());
+        }
+
+        Ok(new)
+    }
+
+    pub fn alloc_young<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        self.alloc_object_in(&self.blocks, || object)
+    }
+
+    pub fn alloc_old<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        self.alloc_object_in(&self.tenured, || object)
+    }
+
+    // builds the object in place once its space is reserved. if `init`
+    // panics the reservation is rolled back before the panic propagates.
+    pub fn alloc_with<T, F>(&self, init: F) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+        F: FnOnce() -> T,
+    {
+        self.alloc_object_in(&self.blocks, init)
+    }
+
+    // skips classifying the object, trusting `class` to be what alloc would
+    // have picked
+    pub fn alloc_in_class<T>(&self, object: T, class: SizeClass) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        if size_of::<T>() == 0 {
+            return self.alloc(object);
+        }
+
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let object_size = size_of::<T>();
+        let align = max(align_of::<T>(), constants::ALLOC_ALIGN);
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let alloc_size = self.alloc_size_of(object_size);
+
+        debug_assert!(
+            SizeClass::get_for_aligned_size(alloc_size, align) == Ok(class),
+            "ALLOC ERROR: object allocated in the wrong size class"
+        );
+
+        let space = blocks.find_aligned_space(alloc_size, class, align, header_alloc_size)?;
+        let header = |size_class| H::new_typed::<T>(size_class, Mark::Allocated);
+        let object_space =
+            self.commit_space_in_class(blocks, space, object_size, alloc_size, class, align, header_alloc_size, header);
+
+        unsafe {
+            write(object_space as *mut T, object);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    fn alloc_object_in<T, F>(&self, space: &UnsafeCell<BlockList>, init: F) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+        F: FnOnce() -> T,
+    {
+        if size_of::<T>() == 0 {
+            #[cfg(feature = "profiling")]
+            self.record_alloc(T::TYPE_ID);
+
+            forget(init());
+
+            return Ok(RawPtr::new(NonNull::<T>::dangling().as_ptr()));
+        }
+
+        let object_size = size_of::<T>();
+        let object_space = self.alloc_space_in(space, object_size, align_of::<T>(), |size_class| {
+            H::new_typed::<T>(size_class, Mark::Allocated)
+        })?;
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let guard = AllocGuard {
+            blocks: space,
+            space: unsafe { object_space.sub(header_alloc_size) },
+            alloc_size: self.alloc_size_of(object_size),
+        };
+        let object = init();
+
+        forget(guard);
+
+        unsafe {
+            write(object_space as *mut T, object);
+
+            Ok(RawPtr::new(object_space as *const T))
+        }
+    }
+
+    pub fn register_trace<T>(&self)
+    where
+        T: Trace + AllocObject<H::TypeId>,
+        H::TypeId: Hash + Eq,
+    {
+        let tracers = unsafe { &mut *self.tracers.get() };
+
+        tracers.insert(T::TYPE_ID, trace_object::<T>);
+    }
+
+    // alloc, for types `registry` can later hand back through get_dyn
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    pub fn alloc_dyn<T, D>(&self, object: T, registry: &DynRegistry<H::TypeId, D>) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+        D: ?Sized + 'static,
+        H::TypeId: Hash + Eq,
+    {
+        // a zero-sized object has no header to find its type id in
+        if size_of::<T>() == 0 || !registry.contains(&T::TYPE_ID) {
+            return Err(AllocError::BadRequest);
+        }
+
+        self.alloc(object)
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must point at a live object in this heap, and nothing may
+    /// mutate or move the object while the returned reference is held.
+    pub unsafe fn get_dyn<D>(&self, ptr: NonNull<()>, registry: &DynRegistry<H::TypeId, D>) -> Option<&D>
+    where
+        D: ?Sized + 'static,
+        H::TypeId: Hash + Eq,
+    {
+        let header = Self::get_header(ptr).as_ref();
+
+        registry.cast(&header.type_id(), ptr).map(|object| &*object)
+    }
+
+    // marking follows `T::PTR_OFFSETS` in every `T`, along with any tracer
+    pub fn register_layout<T>(&self)
+    where
+        T: PointerLayout + AllocObject<H::TypeId>,
+        H::TypeId: Hash + Eq,
+    {
+        let layouts = unsafe { &mut *self.layouts.get() };
+
+        layouts.insert(T::TYPE_ID, T::PTR_OFFSETS);
+    }
+
+    // collect runs `T`'s destructor on each dead `T`
+    pub fn register_drop<T>(&self)
+    where
+        T: AllocObject<H::TypeId>,
+        H::TypeId: Hash + Eq,
+    {
+        let droppers = unsafe { &mut *self.droppers.get() };
+
+        droppers.insert(T::TYPE_ID, drop_object::<T>);
+    }
+
+    // sweep, after dropping every object the last mark phase didn't reach.
+    // dropped objects are forgotten so a later collect can't drop them
+    // again. survivors then lose their marks for the next cycle, going back
+    // to Allocated, since Unmarked would read as dead to alloc_at, is_live
+    // and the next collect. their lines stay marked: that's what keeps
+    // allocation off them until the next mark.
+    pub fn collect(&self)
+    where
+        H::TypeId: Hash + Eq,
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let droppers = unsafe { &*self.droppers.get() };
+
+        for space in [blocks, tenured] {
+            let dead: Vec<*const u8> = space
+                .headers()
+                .filter(|header| unsafe { &*(*header as *const H) }.mark_state() == Mark::Unmarked)
+                .collect();
+
+            for header in dead {
+                let type_id = unsafe { &*(header as *const H) }.type_id();
+
+                if let Some(drop) = droppers.get(&type_id) {
+                    drop(Self::get_object(unsafe { NonNull::new_unchecked(header as *mut H) }));
+                }
+
+                space.forget(header);
+            }
+        }
+
+        self.sweep();
+
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        for header in blocks.headers().chain(tenured.headers()) {
+            let survivor = unsafe { &mut *(header as *mut H) };
+
+            if survivor.mark_state() == Mark::Marked {
+                survivor.reset_mark();
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Every root must point at a live object allocated from this heap, as
+    /// must every child the registered tracers and pointer layouts turn up.
+    pub unsafe fn mark_from_roots(&self, roots: &[NonNull<()>])
+    where
+        H::TypeId: Hash + Eq,
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let tracers = unsafe { &*self.tracers.get() };
+        let layouts = unsafe { &*self.layouts.get() };
+        let mut worklist = roots.to_vec();
+
+        for large in blocks.large.iter().chain(tenured.large.iter()) {
+            unsafe { (*(large.header as *mut H)).unmark() };
+        }
+
+        blocks.advance_mark_generation();
+        tenured.advance_mark_generation();
+
+        for block in blocks.iter().chain(tenured.iter()) {
+            for object in block.objects() {
+                unsafe { (*(object as *mut H)).unmark() };
+            }
+        }
+
+        while let Some(object) = worklist.pop() {
+            let mut header_ptr = Self::get_header(object);
+            let header = unsafe { header_ptr.as_mut() };
+
+            if header.is_marked() {
+                continue;
+            }
+
+            self.mark_header(blocks, tenured, header);
+
+            if let Some(trace) = tracers.get(&header.type_id()) {
+                trace(object, &mut |child| worklist.push(child));
+            }
+
+            if let Some(offsets) = layouts.get(&header.type_id()) {
+                for offset in offsets.iter() {
+                    let child = unsafe { read(object.cast::<u8>().as_ptr().add(*offset) as *const *mut ()) };
+
+                    worklist.extend(NonNull::new(child));
+                }
+            }
+        }
+    }
+
+    /// Marks one object and its lines, for collectors that trace outside
+    /// the crate. `mark_from_roots(&[])` starts the phase and sweep ends it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point at a live object allocated from this heap.
+    pub unsafe fn mark_object(&self, ptr: NonNull<()>) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let header = unsafe { Self::get_header(ptr).as_mut() };
+
+        self.mark_header(blocks, tenured, header);
+    }
+
+    fn mark_header(&self, blocks: &mut BlockList, tenured: &mut BlockList, header: &mut H) {
+        header.mark();
+
+        let mut alloc_size = self.alloc_size_of(header.size() as usize);
+
+        if header.size_class() == SizeClass::Small {
+            alloc_size += blocks.conservative_lines * blocks.line_size;
+        }
+
+        let header_ptr = header as *const H as *const u8;
+
+        if !blocks.mark_span(header_ptr, alloc_size) {
+            tenured.mark_span(header_ptr, alloc_size);
+        }
+    }
+
+    // dead objects of exactly `alloc_size` whose lines are all still marked.
+    // sweep can't hand those lines out as holes, so the slots can be reused
+    // in place until the next mark phase.
+    pub(crate) fn dead_slots(&self, alloc_size: usize) -> Vec<NonNull<H>> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let mut slots = Vec::new();
+
+        for block in blocks.iter() {
+            for object in block.objects() {
+                let header = unsafe { &*(object as *const H) };
+                let size = self.alloc_size_of(header.size() as usize);
+
+                if header.is_marked() || header.is_pinned() || size != alloc_size {
+                    continue;
+                }
+
+                if Self::lines_still_marked(block, object, alloc_size) {
+                    slots.push(unsafe { NonNull::new_unchecked(object as *mut H) });
+                }
+            }
+        }
+
+        slots
+    }
+
+    fn lines_still_marked(block: &BumpBlock, object: *const u8, alloc_size: usize) -> bool {
+        let offset = block.offset_of(object);
+        let first_line = offset / block.line_size();
+        let last_line = (offset + alloc_size - 1) / block.line_size();
+
+        (first_line..=last_line).all(|line_num| block.is_line_marked(line_num))
+    }
+
+    /// Writes `object` over the dead object at `slot`, keeping its address.
+    /// The dead object has to be the same size, and, like the slots from
+    /// `dead_slots`, sit on lines a live neighbour kept marked through the
+    /// sweep. A slot `dealloc` freed also does if the cursor has already
+    /// bumped past it, as the space can't be handed out again before the next
+    /// sweep. Anything else is refused with `BadRequest`.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be an object pointer this heap handed out, so that the
+    /// header in front of it can be read, and nothing may use the dead object
+    /// afterwards.
+    pub unsafe fn alloc_at<T>(&self, object: T, slot: NonNull<()>) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let header = Self::get_header(slot);
+        let start = header.as_ptr() as *const u8;
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let block = match blocks.iter_mut().chain(tenured.iter_mut()).find(|block| block.contains(start)) {
+            Some(block) if align_of::<T>() <= constants::ALLOC_ALIGN => block,
+            _ => return Err(AllocError::BadRequest),
+        };
+
+        let dead = unsafe { header.as_ref() };
+        let alloc_size = self.alloc_size_of(size_of::<T>());
+        let is_start = block.object_start_before(slot.as_ptr() as *const u8) == Some(start);
+        let freed = match dead.mark_state() {
+            // dealloc forgot it, so it's no longer an object start
+            Mark::Freed if !is_start => true,
+            Mark::Unmarked if is_start => false,
+            _ => return Err(AllocError::BadRequest),
+        };
+
+        if dead.is_pinned() || self.alloc_size_of(dead.size() as usize) != alloc_size {
+            return Err(AllocError::BadRequest);
+        }
+
+        let reusable = Self::lines_still_marked(block, start, alloc_size) || (freed && start >= block.cursor());
+
+        if !reusable {
+            return Err(AllocError::BadRequest);
+        }
+
+        if freed {
+            block.restore_object(start);
+        }
+
+        Ok(unsafe { self.alloc_in_slot(header, object) })
+    }
+
+    // writes a fresh header and `object` into a slot from `dead_slots`
+    pub(crate) unsafe fn alloc_in_slot<T>(&self, slot: NonNull<H>, object: T) -> RawPtr<T>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let alloc_size = self.alloc_size_of(size_of::<T>());
+        let size_class = SizeClass::get_for_size(alloc_size).unwrap();
+        let header = H::new_typed::<T>(size_class, Mark::Allocated);
+        let object_space = (slot.as_ptr() as *mut u8).add(header_alloc_size) as *mut T;
+
+        (*self.blocks.get()).bytes_allocated += alloc_size;
+
+        write(slot.as_ptr(), header);
+        self.poison_redzone(slot.as_ptr() as *const u8);
+        write(object_space, object);
+        self.record_alignment(object_space as *const u8, constants::ALLOC_ALIGN, 0);
+
+        #[cfg(feature = "profiling")]
+        self.record_alloc(T::TYPE_ID);
+
+        RawPtr::new(object_space)
+    }
+
+    /// Keeps the object where it is through evacuation and compaction.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point at a live object allocated from this heap, as its
+    /// header is written in place.
+    pub unsafe fn pin<T>(&self, ptr: RawPtr<T>) {
+        let mut header = Self::get_header(ptr.as_untyped());
+
+        header.as_mut().pin();
+    }
+
+    pub fn evacuate<F>(&self, remap: &mut F) -> Result<(), AllocError>
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let mut candidates = blocks.recycle.drain();
+        let mut retained = Vec::new();
+
+        while let Some(mut block) = candidates.pop() {
+            match self.evacuate_block(&mut block, remap) {
+                Ok(true) => retained.push(block),
+
+                Ok(false) => {
+                    block.reset();
+                    blocks.free.push(block);
+                }
+
+                Err(error) => {
+                    retained.push(block);
+                    retained.append(&mut candidates);
+                    blocks.recycle.append(&mut retained);
+
+                    return Err(error);
+                }
+            }
+        }
+
+        blocks.recycle.append(&mut retained);
+
+        Ok(())
+    }
+
+    fn evacuate_block<F>(&self, block: &mut BumpBlock, remap: &mut F) -> Result<bool, AllocError>
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+    {
+        let mut pinned = false;
+        let objects: Vec<*const u8> = block.objects().collect();
+
+        for object in objects {
+            let header = unsafe { &*(object as *const H) };
+
+            if header.is_pinned() {
+                pinned = true;
+                continue;
+            }
+
+            // anything allocated since the last mark is live too
+            if !matches!(header.mark_state(), Mark::Marked | Mark::Allocated) {
+                continue;
+            }
+
+            let alloc_size = self.alloc_size_of(header.size() as usize);
+            let (align, aligned_offset) = self.alignment_of(object);
+            let space = self.find_aligned_space(alloc_size, header.size_class(), align, aligned_offset)?;
+
+            unsafe {
+                copy_nonoverlapping(object, space as *mut u8, alloc_size);
+                (*self.blocks.get()).mark_span(space, alloc_size);
+
+                let old = Self::get_object(NonNull::new_unchecked(object as *mut H));
+                let new = Self::get_object(NonNull::new_unchecked(space as *mut H));
+
+                block.forget_object(object);
+                (*(object as *mut H)).mark_forwarded();
+                write(old.as_ptr() as *mut *mut (), new.as_ptr());
+                self.move_refs(old, new);
+                remap(old, new);
+            }
+        }
+
+        Ok(pinned)
+    }
+
+    // where an object evacuate moved ended up. the forwarding only lasts
+    // until the old block's lines are allocated over again.
+    pub fn follow_forward(&self, ptr: NonNull<()>) -> NonNull<()> {
+        let mut object = ptr;
+
+        while unsafe { Self::get_header(object).as_ref() }.mark_state() == Mark::Forwarded {
+            object = unsafe { NonNull::new_unchecked(read(object.as_ptr() as *const *mut ())) };
+        }
+
+        object
+    }
+
+    // squeezes the live objects of the block at `block_index`, as numbered
+    // by block_index_of, up against its end so its free lines form one hole.
+    // like evacuate, moved objects only keep ALLOC_ALIGN alignment. blocks
+    // holding a pinned object are left alone. returns the bytes moved.
+    pub fn defrag_block<F>(&self, block_index: usize, remap: &mut F) -> usize
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let tenured = unsafe { &mut *self.tenured.get() };
+        let nursery_count = blocks.iter().count();
+        let block = match block_index.checked_sub(nursery_count) {
+            None => blocks.iter_mut().nth(block_index),
+            Some(index) => tenured.iter_mut().nth(index),
+        };
+        let block = match block {
+            Some(block) => block,
+            None => return 0,
+        };
+        let mut live = Vec::new();
+
+        for object in block.objects() {
+            let header = unsafe { &*(object as *const H) };
+
+            if header.is_pinned() {
+                return 0;
+            }
+
+            if header.mark_state() != Mark::Unmarked {
+                let (align, aligned_offset) = self.alignment_of(object);
+
+                live.push((object, self.alloc_size_of(header.size() as usize), align, aligned_offset));
+            }
+        }
+
+        let moved = block.compact(&live);
+        let mut consolidated = 0;
+
+        for ((object, alloc_size, ..), space) in live.into_iter().zip(moved) {
+            if object == space {
+                continue;
+            }
+
+            unsafe {
+                let old = Self::get_object(NonNull::new_unchecked(object as *mut H));
+                let new = Self::get_object(NonNull::new_unchecked(space as *mut H));
+
+                self.move_refs(old, new);
+                remap(old, new);
+            }
+
+            consolidated += alloc_size;
+        }
+
+        consolidated
+    }
+
+    // the header of the allocation `interior` points into, header included.
+    // pointers into free space, the current bump hole among it, give None.
+    pub fn object_base(&self, interior: NonNull<()>) -> Option<NonNull<H>> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+        let ptr = interior.as_ptr() as *const u8;
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let contains = |start: *const u8| {
+            let size = unsafe { &*(start as *const H) }.size() as usize;
+
+            ptr >= start && (ptr as usize) < start as usize + header_alloc_size + size
+        };
+
+        for space in [blocks, tenured] {
+            if let Some(block) = space.iter().find(|block| block.contains(ptr)) {
+                if ptr >= block.limit() && ptr < block.cursor() {
+                    return None;
+                }
+
+                let start = block.object_start_before(ptr).filter(|start| contains(*start))?;
+
+                return NonNull::new(start as *mut H);
+            }
+
+            if let Some(large) = space.large.iter().find(|large| contains(large.header)) {
+                return NonNull::new(large.header as *mut H);
+            }
+        }
+
+        None
+    }
+
+    // whether `ptr` is still an object: freshly allocated since the last
+    // mark, or marked then with its line still occupied. objects swept or
+    // awaiting the sweeper aren't.
+    pub fn is_live(&self, ptr: NonNull<()>) -> bool {
+        let header = Self::get_header(ptr);
+
+        if self.object_base(ptr) != Some(header) {
+            return false;
+        }
+
+        let header = unsafe { header.as_ref() };
+
+        match header.mark_state() {
+            Mark::Allocated => true,
+            Mark::Unmarked | Mark::Freed | Mark::Forwarded => false,
+            Mark::Marked => {
+                let blocks = unsafe { &*self.blocks.get() };
+                let tenured = unsafe { &*self.tenured.get() };
+                let start = header as *const H as *const u8;
+
+                match blocks.iter().chain(tenured.iter()).find(|block| block.contains(start)) {
+                    Some(block) => block.is_line_marked(block.offset_of(start) / block.line_size()),
+                    None => header.size_class() == SizeClass::Large,
+                }
+            }
+        }
+    }
+
+    // a lighter pass than evacuate: the live objects of the emptiest recycle
+    // blocks move into the holes of the fullest ones, and each block emptied
+    // that way is freed
+    pub fn consolidate_recycle<F>(&self, remap: &mut F)
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+    {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let mut targets = blocks.recycle.drain();
+
+        targets.sort_by_key(|block| Reverse(block.occupied_lines()));
+
+        while targets.len() > 1 {
+            let mut source = targets.pop().unwrap();
+            let live: Vec<*const u8> = source
+                .objects()
+                .filter(|object| matches!(unsafe { &*(*object as *const H) }.mark_state(), Mark::Marked | Mark::Allocated))
+                .collect();
+
+            if live.iter().any(|object| unsafe { &*(*object as *const H) }.is_pinned()) {
+                blocks.recycle.push(source);
+                continue;
+            }
+
+            let mut emptied = true;
+
+            for object in live {
+                let header = unsafe { &*(object as *const H) };
+                let alloc_size = self.alloc_size_of(header.size() as usize);
+                let (align, aligned_offset) = self.alignment_of(object);
+                let found = targets.iter_mut().find_map(|target| {
+                    let space = target.inner_alloc_aligned(alloc_size, align, aligned_offset)?;
+
+                    Some((target, space))
+                });
+                let (target, space) = match found {
+                    Some(found) => found,
+                    None => {
+                        emptied = false;
+                        break;
+                    }
+                };
+
+                unsafe {
+                    copy_nonoverlapping(object, space as *mut u8, alloc_size);
+                    target.mark_span(space, alloc_size);
+
+                    let old = Self::get_object(NonNull::new_unchecked(object as *mut H));
+                    let new = Self::get_object(NonNull::new_unchecked(space as *mut H));
+
+                    source.forget_object(object);
+                    self.move_refs(old, new);
+                    remap(old, new);
+                }
+            }
+
+            if emptied {
+                source.reset();
+                blocks.free.push(source);
+            } else {
+                // anything fuller won't fit either
+                blocks.recycle.push(source);
+                break;
+            }
+        }
+
+        blocks.recycle.append(&mut targets);
+    }
+
+    // copies every marked object into `dest`, leaving this heap untouched.
+    // the copies start out unmarked, as if freshly allocated in `dest`.
+    pub fn copy_live_into<F>(&self, dest: &ZapHeap<H>, remap: &mut F) -> Result<(), AllocError>
+    where
+        F: FnMut(NonNull<()>, NonNull<()>),
+    {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        for object in blocks.headers().chain(tenured.headers()) {
+            let header = unsafe { &*(object as *const H) };
+
+            if !header.is_marked() {
+                continue;
+            }
+
+            let alloc_size = self.alloc_size_of(header.size() as usize);
+            let (align, aligned_offset) = self.alignment_of(object);
+            let space = dest.find_aligned_space(alloc_size, header.size_class(), align, aligned_offset)?;
+
+            unsafe {
+                // a new allocation as far as `dest` is concerned, so no pin
+                // or mark carries over. the rest of the header does.
+                copy_nonoverlapping(object, space as *mut u8, alloc_size);
+
+                let copy = &mut *(space as *mut H);
+
+                copy.reset_mark();
+                copy.unpin();
+                (*dest.blocks.get()).bytes_allocated += alloc_size;
+
+                let old = Self::get_object(NonNull::new_unchecked(object as *mut H));
+                let new = Self::get_object(NonNull::new_unchecked(space as *mut H));
+
+                dest.record_alignment(new.as_ptr() as *const u8, align, aligned_offset);
+                remap(old, new);
+            }
+        }
+
+        Ok(())
+    }
+
+    // walks every block checking the allocator's own invariants. line marks
+    // are only checked for marked objects, so this holds before marking too.
+    pub fn verify(&self) -> Result<(), HeapError> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let tenured = unsafe { &*self.tenured.get() };
+
+        for space in [blocks, tenured] {
+            for block in space.iter() {
+                if block.limit() > block.cursor() || block.offset_of(block.cursor()) > constants::BLOCK_CAPACITY {
+                    return Err(HeapError::BadCursor(block.cursor()));
+                }
+
+                let mut previous_end = 0;
+
+                for object in block.objects() {
+                    let header = unsafe { &*(object as *const H) };
+                    let alloc_size = self.alloc_size_of(header.size() as usize);
+                    let offset = block.offset_of(object);
+
+                    if SizeClass::get_for_size(alloc_size) != Ok(header.size_class()) {
+                        return Err(HeapError::BadSizeClass(object));
+                    }
+
+                    if offset + alloc_size > constants::BLOCK_CAPACITY {
+                        return Err(HeapError::ObjectInMetadata(object));
+                    }
+
+                    if offset < previous_end {
+                        return Err(HeapError::OverlappingObjects(object));
+                    }
+
+                    previous_end = offset + alloc_size;
+
+                    if !header.is_marked() {
+                        continue;
+                    }
+
+                    let first_line = offset / block.line_size();
+                    let last_line = (offset + alloc_size - 1) / block.line_size();
+
+                    if !block.is_marked() || !(first_line..=last_line).all(|line| block.is_line_marked(line)) {
+                        return Err(HeapError::UnmarkedLine(object));
+                    }
+                }
+            }
+
+            for large in space.large.iter() {
+                if unsafe { &*(large.header as *const H) }.size_class() != SizeClass::Large {
+                    return Err(HeapError::BadSizeClass(large.header));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // an independent heap holding copies of every marked object, plus a
+    // lookup from old object pointers to their copies. pointers that weren't
+    // copied map to themselves.
+    #[allow(clippy::type_complexity)]
+    pub fn deep_copy(&self) -> Result<(ZapHeap<H>, impl Fn(NonNull<()>) -> NonNull<()>), AllocError> {
+        let blocks = unsafe { &*self.blocks.get() };
+        let mut copy = ZapHeap::empty();
+        let mut remapped = BTreeMap::new();
+
+        copy.redzone = self.redzone;
+        copy.set_hole_strategy(blocks.hole_strategy);
+        copy.set_side_metadata(blocks.side_metadata);
+        copy.set_conservative_lines(blocks.conservative_lines);
+
+        unsafe {
+            (*copy.blocks.get()).source = blocks.source.clone();
+            (*copy.tenured.get()).source = blocks.source.clone();
+            (*copy.blocks.get()).line_size = blocks.line_size;
+            (*copy.tenured.get()).line_size = blocks.line_size;
+        }
+
+        if let Some(threshold) = blocks.gc_threshold {
+            copy.set_gc_threshold(threshold);
+        }
+
+        unsafe {
+            *copy.tracers.get() = (*self.tracers.get()).clone();
+            *copy.layouts.get() = (*self.layouts.get()).clone();
+        }
+
+        self.copy_live_into(&copy, &mut |old, new| {
+            remapped.insert(old, new);
+        })?;
+
+        Ok((copy, move |old| remapped.get(&old).copied().unwrap_or(old)))
+    }
+}
+
+impl<H: AllocHeader> AllocRaw for ZapHeap<H> {
+    type Header = H;
+
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<<Self::Header as AllocHeader>::TypeId>,
+    {
+        let ptr = self.alloc_object_in(&self.blocks, || object)?;
+
+        #[cfg(feature = "debug-alloc-sites")]
+        if size_of::<T>() != 0 {
+            self.record_site(ptr.as_untyped());
+        }
+
+        Ok(ptr)
+    }
+
+    #[cfg_attr(feature = "debug-alloc-sites", track_caller)]
+    fn alloc_array(&self, size_bytes: ArraySize) -> Result<RawPtr<u8>, AllocError> {
+        let array_space = self.alloc_array_space(size_bytes, constants::ALLOC_ALIGN)?;
+
+        unsafe {
+            let array = from_raw_parts_mut(array_space as *mut u8, size_bytes as usize);
+            for byte in array {
+                *byte = 0;
+            }
+        }
+
+        #[cfg(feature = "debug-alloc-sites")]
+        self.record_site(unsafe { NonNull::new_unchecked(array_space as *mut ()) });
+
+        Ok(RawPtr::new(array_space))
+    }
+
+    // the payload sits a padded header past the header, which is more than
+    // one `Self::Header` when its size isn't a multiple of ALLOC_ALIGN
+    fn get_header(object: NonNull<()>) -> NonNull<Self::Header> {
+        let header_alloc_size = add_alignment_padding(size_of::<Self::Header>());
+
+        unsafe { NonNull::new_unchecked(object.cast::<u8>().as_ptr().sub(header_alloc_size).cast()) }
+    }
+
+    fn get_object(header: NonNull<Self::Header>) -> NonNull<()> {
+        let header_alloc_size = add_alignment_padding(size_of::<Self::Header>());
+
+        unsafe { NonNull::new_unchecked(header.cast::<u8>().as_ptr().add(header_alloc_size).cast()) }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<H: AllocHeader> Default for ZapHeap<H> {
+    fn default() -> ZapHeap<H> {
+        ZapHeap::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::allocator::{AllocTypeId, SizeClass};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone)]
+    pub(crate) struct SmallTestObj {
+        pub(crate) data: u32,
+    }
+
+    struct MediumTestObj {
+        data: [u8; 256],
+    }
+
+    pub(crate) fn alloc_size<T>() -> usize {
+        let header_size = size_of::<TestHeader>();
+        let header_alloc_size = add_alignment_padding(header_size);
+        let object_size = size_of::<T>();
+        let total_size = header_alloc_size + object_size;
+        let alloc_size = add_alignment_padding(total_size);
+        /*
+            let alignment = size_of::<usize>(); 
+            println!("ALIGNMENT: {}", alignment);       // 8
+            println!("HEADER_SIZE: {}", header_size);   // 8
+            println!("HEADER_ALLOC_SIZE: {}", header_alloc_size);   // 8
+            println!("OBJECT_SIZE: {}", object_size);   // 4
+            println!("TOTAL_SIZE:  {}",  total_size);   // 12
+            println!("ALLOC_SIZE:  {}",  alloc_size);   // 16
+        */
+        alloc_size
+    }
+
+    impl AllocObject<TestTypeId> for MediumTestObj {
+        const TYPE_ID: TestTypeId = TestTypeId::Medium;
+    }
+
+    impl AllocObject<TestTypeId> for SmallTestObj {
+        const TYPE_ID: TestTypeId = TestTypeId::Small;
+    }
+
+    #[derive(PartialEq, Eq, Hash, Copy, Clone)]
+    pub(crate) enum TestTypeId {
+        Small,
+        Medium,
+        Large,
+        Array,
+    }
+
+    impl AllocTypeId for TestTypeId {}
+
+    pub(crate) struct TestHeader {
+        mark: Mark,
+        type_id: TestTypeId,
+        size: u32,
+        size_class: SizeClass,
+        pinned: bool,
+    }
+
+    impl AllocHeader for TestHeader {
+        type TypeId = TestTypeId;
+
+        fn new<O: AllocObject<Self::TypeId>>(size: u32, size_class: SizeClass, mark: Mark) -> Self {
+            TestHeader {
+                type_id: O::TYPE_ID,
+                mark,
+                size,
+                size_class,
+                pinned: false,
+            }
+        }
+
+        fn new_array(size: u32, size_class: SizeClass, mark: Mark) -> Self {
+            TestHeader {
+                type_id: TestTypeId::Array,
+                mark,
+                size,
+                size_class,
+                pinned: false,
+            }
+        }
+        fn new_bytes(type_id: TestTypeId, size: u32, size_class: SizeClass, mark: Mark) -> Self {
+            TestHeader {
+                type_id,
+                mark,
+                size,
+                size_class,
+                pinned: false,
+            }
+        }
+
+        fn mark(&mut self) {
+            self.mark = Mark::Marked;
+        }
+
+        fn unmark(&mut self) {
+            self.mark = Mark::Unmarked;
+        }
+
+        fn mark_freed(&mut self) {
+            self.mark = Mark::Freed;
+        }
+
+        fn mark_forwarded(&mut self) {
+            self.mark = Mark::Forwarded;
+        }
+
+        fn reset_mark(&mut self) {
+            self.mark = Mark::Allocated;
+        }
+
+        fn is_marked(&self) -> bool {
+            self.mark == Mark::Marked
+        }
+
+        fn mark_state(&self) -> Mark {
+            self.mark
+        }
+
+        fn pin(&mut self) {
+            self.pinned = true;
+        }
+
+        fn unpin(&mut self) {
+            self.pinned = false;
+        }
+
+        fn is_pinned(&self) -> bool {
+            self.pinned
+        }
+
+        fn type_id(&self) -> Self::TypeId {
+            self.type_id
+        }
+
+        fn size(&self) -> u32 {
+            self.size }
+
+        fn size_class(&self) -> SizeClass {
+            self.size_class
+        }
+    }
+
+    #[test]
+    fn test_alloc_small_obj() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small_obj = SmallTestObj { data: 333};
+        let ptr = heap.alloc(small_obj);
+        let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let alloc_size = alloc_size::<SmallTestObj>();
+
+        assert!(small_obj_copy.data == 333);
+        assert!(blocks.block_count() == 1);
+        assert!(blocks.head.is_some());
+        assert!(blocks.head.as_ref().unwrap().current_hole_size() == constants::BLOCK_CAPACITY - alloc_size);
+    }
+
+    #[test]
+    fn test_alloc_many_small_obj() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let alloc_size = alloc_size::<SmallTestObj>();
+
+        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
+            let small_obj = SmallTestObj { data: 333};
+            let ptr = heap.alloc(small_obj);
+            let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
+            assert!(small_obj_copy.data == 333);
+        }
+
+        assert!(blocks.block_count() == 1);
+        assert!(blocks.head.is_some());
+        assert!(blocks.used.is_empty());
+        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+
+        let small_obj = SmallTestObj { data: 333};
+        let ptr = heap.alloc(small_obj);
+        let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
+
+        assert!(small_obj_copy.data == 333);
+        assert!(blocks.block_count() == 2);
+        assert!(blocks.used.len() == 1);
+        assert!(blocks.head.is_some());
+        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+    }
+
+    #[test]
+    fn test_small_obj_header() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small_obj = SmallTestObj { data: 333};
+        let raw_ptr = heap.alloc(small_obj).unwrap();
+        let header_ptr = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let header: &TestHeader = unsafe { &*header_ptr.as_ptr() };
+
+        assert!(header.type_id == TestTypeId::Small);
+        assert!(header.size == size_of::<SmallTestObj>() as u32);
+        assert!(header.size_class == SizeClass::Small);
+        assert!(header.mark == Mark::Allocated);
+    }
+
+    #[test]
+    fn test_get_object() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small_obj = SmallTestObj { data: 333};
+        let raw_ptr = heap.alloc(small_obj).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let obj_ptr = ZapHeap::get_object(header_ptr);
+        let obj = unsafe { &*(obj_ptr.as_ptr() as *const SmallTestObj) };
+
+        assert!(obj.data == 333);
+    }
+
+    #[test]
+    fn test_alloc_medium_object() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small_obj = MediumTestObj { data: [9; 256] };
+        let raw_ptr = heap.alloc(small_obj).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let obj_ptr = ZapHeap::get_object(header_ptr);
+        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+        assert!(obj.data == [9; 256]);
+    }
+
+    #[test]
+    fn test_over_flow_alloc() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let alloc_size = alloc_size::<MediumTestObj>();
+
+        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
+            let medium_obj = MediumTestObj { data: [9; 256] };
+            let raw_ptr = heap.alloc(medium_obj).unwrap();
+            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+            let obj_ptr = ZapHeap::get_object(header_ptr);
+            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+            assert!(obj.data == [9; 256]);
+        }
+
+        assert!(blocks.block_count() == 1);
+        assert!(blocks.head.is_none());
+        assert!(blocks.recycle.is_empty());
+        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+
+        let medium_obj = MediumTestObj { data: [9; 256] };
+        let raw_ptr = heap.alloc(medium_obj).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let obj_ptr = ZapHeap::get_object(header_ptr);
+        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+        assert!(obj.data == [9; 256]);
+        assert!(blocks.block_count() == 2);
+        assert!(blocks.overflow.is_some());
+        assert!(blocks.recycle.len() == 1);
+        assert!(blocks.head.is_none());
+        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+    }
+
+    #[test]
+    fn test_size_classes_keep_their_own_blocks() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let mut small = Vec::new();
+        let mut medium = Vec::new();
+
+        for data in 0..40 {
+            small.push(heap.alloc(SmallTestObj { data }).unwrap());
+            medium.push(heap.alloc(MediumTestObj { data: [data as u8; 256] }).unwrap());
+        }
+
+        let head = blocks.head.as_ref().unwrap().base();
+        let overflow = blocks.overflow.as_ref().unwrap().base();
+        let block_base = |ptr: NonNull<()>| (ptr.as_ptr() as usize & !(constants::BLOCK_SIZE - 1)) as *const u8;
+
+        assert!(head != overflow);
+        assert!(small.iter().all(|ptr| block_base(ptr.as_untyped()) == head));
+        assert!(medium.iter().all(|ptr| block_base(ptr.as_untyped()) == overflow));
+        assert!(blocks.used.is_empty());
+    }
+
+    #[test]
+    fn test_use_recycling() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let alloc_size = alloc_size::<MediumTestObj>();
+
+        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
+            let medium_obj = MediumTestObj { data: [9; 256] };
+            let raw_ptr = heap.alloc(medium_obj).unwrap();
+            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+            let obj_ptr = ZapHeap::get_object(header_ptr);
+            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+            assert!(obj.data == [9; 256]);
+        }
+
+        assert!(blocks.block_count() == 1);
+        assert!(blocks.head.is_none());
+        assert!(blocks.recycle.is_empty());
+        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+
+        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
+            let medium_obj = MediumTestObj { data: [9; 256] };
+            let raw_ptr = heap.alloc(medium_obj).unwrap();
+            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+            let obj_ptr = ZapHeap::get_object(header_ptr);
+            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+            assert!(obj.data == [9; 256]);
+        }
+
+        assert!(blocks.block_count() == 2);
+        assert!(blocks.overflow.is_some());
+        assert!(blocks.recycle.len() == 1);
+        assert!(blocks.head.is_none());
+        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+
+        let medium_obj = MediumTestObj { data: [9; 256] };
+        let raw_ptr = heap.alloc(medium_obj).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let obj_ptr = ZapHeap::get_object(header_ptr);
+        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+
+        assert!(obj.data == [9; 256]);
+        assert!(blocks.block_count() == 3);
+        assert!(blocks.overflow.is_some());
+        assert!(blocks.recycle.len() == 2);
+        assert!(blocks.head.is_none());
+        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+    }
+
+    #[test]
+    fn test_array_alloc() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let alloc_size = size_of::<MediumTestObj>() as u32;
+        let raw_ptr = heap.alloc_array(alloc_size as u32).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
+        let header = unsafe { &*header_ptr.as_ptr() };
+
+        assert!(header.type_id == TestTypeId::Array);
+        assert!(header.size_class == SizeClass::Medium);
+        assert!(header.mark == Mark::Allocated);
+        assert!(header.size == alloc_size);
+    }
+
+    fn mark<T>(ptr: RawPtr<T>) {
+        let mut header: NonNull<TestHeader> = ZapHeap::get_header(ptr.as_untyped());
+
+        unsafe { header.as_mut().mark() };
+    }
+
+    #[test]
+    fn test_evacuate_skips_pinned_objects() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let pinned = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let live = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        unsafe { heap.mark_from_roots(&[pinned.as_untyped(), live.as_untyped()]) };
+        unsafe { heap.pin(pinned) };
+
+        let sparse = blocks.head.take().unwrap();
+        blocks.recycle.push(sparse);
+
+        let mut moved = Vec::new();
+        heap.evacuate(&mut |old, new| moved.push((old, new))).unwrap();
+
+        let pinned_copy = unsafe { &*pinned.as_ptr() };
+        let (old, new) = moved[0];
+        let live_copy = unsafe { &*(new.as_ptr() as *const SmallTestObj) };
+
+        assert!(pinned_copy.data == 1);
+        assert!(moved.len() == 1);
+        assert!(old == live.as_untyped());
+        assert!(new != live.as_untyped());
+        assert!(live_copy.data == 2);
+        assert!(blocks.recycle.len() == 1);
+        assert!(blocks.free.is_empty());
+    }
+
+    #[test]
+    fn test_handles_follow_evacuation() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let first = heap.alloc_handle(SmallTestObj { data: 1 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        let second = heap.alloc_handle(SmallTestObj { data: 3 }).unwrap();
+        let before = [heap.resolve(first), heap.resolve(second)];
+
+        unsafe { heap.mark_from_roots(&before) };
+
+        let sparse = blocks.head.take().unwrap();
+        blocks.recycle.push(sparse);
+
+        heap.evacuate(&mut |_, _| {}).unwrap();
+
+        let first_copy = unsafe { &*(heap.resolve(first).as_ptr() as *const SmallTestObj) };
+        let second_copy = unsafe { &*(heap.resolve(second).as_ptr() as *const SmallTestObj) };
+
+        assert!(heap.resolve(first) != before[0]);
+        assert!(heap.resolve(second) != before[1]);
+        assert!(first_copy.data == 1);
+        assert!(second_copy.data == 3);
+    }
+
+    #[test]
+    fn test_sweep_forgets_dead_handles() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc_handle(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc_handle(SmallTestObj { data: 2 }).unwrap();
+        let slot = heap.resolve(dead);
+
+        unsafe { heap.mark_from_roots(&[heap.resolve(live)]) };
+        heap.sweep();
+
+        // the live neighbour keeps the line marked, so the slot can be reused
+        let reused = unsafe { heap.alloc_at(SmallTestObj { data: 3 }, slot) }.unwrap();
+        let moved = unsafe { heap.relocate(reused) }.unwrap();
+
+        assert!(reused.as_untyped() == slot);
+        assert!(moved.as_untyped() != slot);
+        assert!(heap.resolve(dead) == slot);
+        assert!(heap.resolve(live) != slot);
+    }
+
+    #[test]
+    fn test_evacuate_leaves_forwarding_pointer() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let live = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let unmoved = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+
+        let sparse = blocks.head.take().unwrap();
+        blocks.recycle.push(sparse);
+
+        let mut moved = Vec::new();
+        heap.evacuate(&mut |old, new| moved.push((old, new))).unwrap();
+
+        let old_header: &TestHeader = unsafe { ZapHeap::get_header(live.as_untyped()).as_ref() };
+        let forwarded = heap.follow_forward(live.as_untyped());
+
+        assert!(old_header.mark_state() == Mark::Forwarded);
+        assert!(forwarded == moved[0].1);
+        assert!(unsafe { &*(forwarded.as_ptr() as *const SmallTestObj) }.data == 2);
+        assert!(heap.follow_forward(forwarded) == forwarded);
+        assert!(heap.follow_forward(unmoved.as_untyped()) == unmoved.as_untyped());
+    }
+
+    #[repr(align(64))]
+    struct AlignedTestObj {
+        data: u32,
+    }
+
+    impl AllocObject<TestTypeId> for AlignedTestObj {
+        const TYPE_ID: TestTypeId = TestTypeId::Small;
+    }
+
+    #[test]
+    fn test_evacuate_keeps_alignment() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        let aligned = heap.alloc(AlignedTestObj { data: 2 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[aligned.as_untyped()]) };
+
+        // knock the new head's cursor off a 64 byte boundary
+        blocks.recycle.push(blocks.head.take().unwrap());
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        let mut moved = Vec::new();
+        heap.evacuate(&mut |old, new| moved.push((old, new))).unwrap();
+
+        let (old, new) = moved[0];
+
+        assert!(moved.len() == 1);
+        assert!(old == aligned.as_untyped());
+        assert!((new.as_ptr() as usize).is_multiple_of(64));
+        assert!(unsafe { &*(new.as_ptr() as *const AlignedTestObj) }.data == 2);
+    }
+
+    #[test]
+    fn test_evacuate_frees_unpinned_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let live = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+
+        let sparse = blocks.head.take().unwrap();
+        blocks.recycle.push(sparse);
+
+        let mut moved = Vec::new();
+        heap.evacuate(&mut |old, new| moved.push((old, new))).unwrap();
+
+        assert!(moved.len() == 1);
+        assert!(blocks.recycle.is_empty());
+        assert!(blocks.free.len() == 1);
+        assert!(blocks.head.is_some());
+    }
+
+    #[test]
+    fn test_should_collect_after_threshold() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let alloc_size = alloc_size::<SmallTestObj>();
+
+        assert!(!heap.should_collect());
+
+        heap.set_gc_threshold(alloc_size * 3);
+
+        for _ in 0..2 {
+            heap.alloc(SmallTestObj { data: 333 }).unwrap();
+        }
+
+        assert!(!heap.should_collect());
+
+        heap.alloc(SmallTestObj { data: 333 }).unwrap();
+
+        assert!(heap.should_collect());
+
+        heap.sweep();
+
+        assert!(!heap.should_collect());
+    }
+
+    #[test]
+    fn test_sweep_sorts_retired_blocks() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        for _ in 0..3 {
+            let mut block = BumpBlock::new().unwrap();
+
+            for _ in 0..constants::LINE_COUNT {
+                block.inner_alloc(constants::LINE_SIZE).unwrap();
+            }
+
+            blocks.used.push(block);
+        }
+
+        for i in 0..constants::LINE_COUNT {
+            blocks.used[0].mark_line(i);
+        }
+
+        blocks.used[0].mark_block();
+        blocks.used[1].mark_line(10);
+        blocks.used[1].mark_block();
+
+        heap.sweep();
+
+        assert!(blocks.used.len() == 1);
+        assert!(blocks.recycle.len() == 1);
+        assert!(blocks.free.len() == 1);
+        let recycled = blocks.recycle.iter().next().unwrap();
+
+        assert!(recycled.current_hole_size() == 0);
+        assert!(recycled.has_hole());
+    }
+
+    #[test]
+    fn test_alloc_slice() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let raw_ptr = heap.alloc_slice::<u64>(100, 0xdeadbeef).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped());
+        let header = unsafe { &*header_ptr.as_ptr() };
+        let slice = unsafe { std::slice::from_raw_parts(raw_ptr.as_ptr(), 100) };
+
+        assert!(slice.iter().all(|element| *element == 0xdeadbeef));
+        assert!(header.type_id == TestTypeId::Array);
+        assert!(header.size == 100 * size_of::<u64>() as u32);
+        assert!(header.size_class == SizeClass::Medium);
+    }
+
+    #[test]
+    fn test_alloc_slice_aligns_elements() {
+        #[derive(Copy, Clone, PartialEq)]
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc(SmallTestObj { data: 333 }).unwrap();
+
+        let raw_ptr = heap.alloc_slice(3, Aligned(7)).unwrap();
+        let slice = unsafe { std::slice::from_raw_parts(raw_ptr.as_ptr(), 3) };
+
+        assert!(raw_ptr.as_word().is_multiple_of(64));
+        assert!(slice.iter().all(|element| *element == Aligned(7)));
+    }
+
+    #[test]
+    fn test_alloc_slice_overflow() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let result = heap.alloc_slice::<u64>(usize::MAX / 4, 0);
+
+        assert!(result.err().unwrap() == AllocError::BadRequest);
+    }
+
+    #[test]
+    fn test_alloc_zero_sized_objects() {
+        struct ZeroSizedObj;
+
+        impl AllocObject<TestTypeId> for ZeroSizedObj {
+            const TYPE_ID: TestTypeId = TestTypeId::Small;
+        }
+
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        for _ in 0..1000 {
+            let raw_ptr = heap.alloc(ZeroSizedObj).unwrap();
+
+            assert!(raw_ptr.as_ptr() == NonNull::<ZeroSizedObj>::dangling().as_ptr());
+        }
+
+        assert!(blocks.block_count() == 0);
+        assert!(blocks.bytes_allocated == 0);
+    }
+
+    #[test]
+    fn test_sweep_frees_unmarked_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let mut block = BumpBlock::new().unwrap();
+
+        block.inner_alloc(constants::BLOCK_CAPACITY).unwrap();
+        blocks.used.push(block);
+
+        heap.sweep();
+
+        assert!(blocks.used.is_empty());
+        assert!(blocks.free.len() == 1);
+        assert!(blocks.free[0].current_hole_size() == constants::BLOCK_CAPACITY);
+    }
+
+    #[test]
+    fn test_sweep_trusts_block_mark_over_line_marks() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let mut block = BumpBlock::new().unwrap();
+
+        block.inner_alloc(constants::BLOCK_CAPACITY).unwrap();
+
+        // a stale line mark is ignored when the block byte was never set
+        for i in 0..constants::LINE_COUNT {
+            block.mark_line(i);
+        }
+
+        blocks.used.push(block);
+
+        heap.sweep();
+
+        assert!(blocks.used.is_empty());
+        assert!(blocks.free.len() == 1);
+        assert!(!blocks.free[0].has_marked_lines());
+    }
+
+    #[test]
+    fn test_alloc_bytes_alignments() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        for align in [1, 8, 16, 64, 128] {
+            let raw_ptr = heap.alloc_bytes(24, align, TestTypeId::Large).unwrap();
+            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped());
+            let header = unsafe { &*header_ptr.as_ptr() };
+
+            assert!(raw_ptr.as_word().is_multiple_of(align));
+            assert!(header.type_id == TestTypeId::Large);
+            assert!(header.size == 24);
+            assert!(header.size_class == SizeClass::Small);
+        }
+    }
+
+    #[test]
+    fn test_alloc_bytes_across_lines() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let size = constants::LINE_SIZE + 72;
+
+        heap.alloc_bytes(24, 8, TestTypeId::Small).unwrap();
+
+        let raw_ptr = heap.alloc_bytes(size, 8, TestTypeId::Large).unwrap();
+        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped());
+        let header = unsafe { &*header_ptr.as_ptr() };
+        let first_line = raw_ptr.as_word() / constants::LINE_SIZE;
+        let last_line = (raw_ptr.as_word() + size - 1) / constants::LINE_SIZE;
+        let bytes = unsafe { from_raw_parts_mut(raw_ptr.as_ptr() as *mut u8, size) };
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        assert!(last_line > first_line);
+        assert!(header.size == size as u32);
+        assert!(header.size_class == SizeClass::Medium);
+        assert!(bytes.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+    }
+
+    #[test]
+    fn test_alloc_bytes_bad_alignment() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let result = heap.alloc_bytes(24, 3, TestTypeId::Large);
+
+        assert!(result.err().unwrap() == AllocError::BadRequest);
+    }
+
+    struct Node {
+        next: Option<NonNull<()>>,
+        data: u32,
+    }
+
+    impl AllocObject<TestTypeId> for Node {
+        const TYPE_ID: TestTypeId = TestTypeId::Medium;
+    }
+
+    impl Trace for Node {
+        fn trace(&self, mark: &mut dyn FnMut(NonNull<()>)) {
+            if let Some(next) = self.next {
+                mark(next);
+            }
+        }
+    }
+
+    pub(crate) fn is_marked(object: NonNull<()>) -> bool {
+        let header: NonNull<TestHeader> = ZapHeap::get_header(object);
+
+        unsafe { header.as_ref().is_marked() }
+    }
+
+    #[test]
+    fn test_mark_from_roots() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        heap.register_trace::<Node>();
+
+        let c = heap.alloc(Node { next: None, data: 3 }).unwrap().as_untyped();
+        let b = heap.alloc(Node { next: Some(c), data: 2 }).unwrap().as_untyped();
+        let a = heap.alloc(Node { next: Some(b), data: 1 }).unwrap().as_untyped();
+        let unreachable = heap.alloc(Node { next: Some(a), data: 0 }).unwrap().as_untyped();
+
+        unsafe { heap.mark_from_roots(&[a]) };
+
+        let head = blocks.head.as_ref().unwrap();
+        let header_line = head.offset_of(ZapHeap::<TestHeader>::get_header(c).as_ptr() as *const u8)
+            / constants::LINE_SIZE;
+
+        assert!(is_marked(a) && is_marked(b) && is_marked(c));
+        assert!(!is_marked(unreachable));
+        assert!(head.is_marked());
+        assert!(head.is_line_marked(header_line));
+        assert!(unsafe { c.cast::<Node>().as_ref() }.data == 3);
+    }
+
+    #[test]
+    fn test_mark_from_roots_clears_previous_marks() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.register_trace::<Node>();
+
+        let b = heap.alloc(Node { next: None, data: 2 }).unwrap();
+        let mut a = heap.alloc(Node { next: Some(b.as_untyped()), data: 1 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[a.as_untyped()]) };
+
+        assert!(is_marked(b.as_untyped()));
+
+        unsafe { a.as_mut_ref().next = None };
+        unsafe { heap.mark_from_roots(&[a.as_untyped()]) };
+
+        assert!(is_marked(a.as_untyped()));
+        assert!(!is_marked(b.as_untyped()));
+    }
+
+    #[test]
+    fn test_alloc_at_block_capacity_boundary() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let max_size = constants::MEDIUM_OBJECT_MAX - header_alloc_size;
+
+        assert!(heap.alloc_bytes(max_size, 8, TestTypeId::Medium).is_ok());
+
+        let large = heap.alloc_bytes(max_size + 1, 8, TestTypeId::Medium).unwrap();
+        let header = unsafe { ZapHeap::<TestHeader>::get_header(large.as_untyped()).as_ref() };
+
+        assert!(header.size_class() == SizeClass::Large);
+    }
+
+    #[test]
+    fn test_alloc_fills_whole_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let size = constants::BLOCK_CAPACITY - header_alloc_size;
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        for _ in 0..2 {
+            let raw_ptr = heap.alloc_bytes(size, 8, TestTypeId::Medium).unwrap();
+            let header = ZapHeap::<TestHeader>::get_header(raw_ptr.as_untyped()).as_ptr() as *const u8;
+            let block = blocks.block_for(header).unwrap();
+
+            assert!(header == block.base());
+            assert!(block.current_hole_size() == 0);
+        }
+
+        assert!(blocks.iter().count() == 2);
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_alignment_padding_promotes_to_large() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let max_size = constants::MEDIUM_OBJECT_MAX - header_alloc_size;
+
+        // medium by size, but the alignment padding no longer fits a block
+        let misaligned = heap.alloc_bytes(max_size, 16, TestTypeId::Medium).unwrap();
+        let huge_align = heap.alloc_bytes(8, constants::BLOCK_SIZE, TestTypeId::Small).unwrap();
+
+        for (ptr, align) in [(misaligned, 16), (huge_align, constants::BLOCK_SIZE)] {
+            let header = unsafe { ZapHeap::<TestHeader>::get_header(ptr.as_untyped()).as_ref() };
+
+            assert!(header.size_class() == SizeClass::Large);
+            assert!(ptr.as_word().is_multiple_of(align));
+        }
+
+        assert!(SizeClass::get_for_aligned_size(max_size + header_alloc_size, 8) == Ok(SizeClass::Medium));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_overflow_alloc_too_big() {
+        let mut blocks = BlockList::new();
+        let result = blocks.overflow_alloc(constants::BLOCK_CAPACITY + 8, 8, 0);
+
+        assert!(result.err().unwrap() == AllocError::BadRequest);
+    }
+
+    #[test]
+    fn test_copy_live_into_gives_fresh_headers() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let dest = ZapHeap::<TestHeader>::new();
+
+        dest.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        let aligned = heap.alloc(AlignedTestObj { data: 2 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[aligned.as_untyped()]) };
+        unsafe { heap.pin(aligned) };
+
+        let mut moved = Vec::new();
+        heap.copy_live_into(&dest, &mut |old, new| moved.push((old, new))).unwrap();
+
+        let (_, new) = moved[0];
+        let header: &TestHeader = unsafe { ZapHeap::get_header(new).as_ref() };
+
+        assert!(moved.len() == 1);
+        assert!(header.mark_state() == Mark::Allocated);
+        assert!(!header.is_pinned());
+        assert!((new.as_ptr() as usize).is_multiple_of(64));
+        assert!(dest.alignment_of(header as *const TestHeader as *const u8) == (64, alloc_size::<()>()));
+        assert!(unsafe { &*(new.as_ptr() as *const AlignedTestObj) }.data == 2);
+    }
+
+    #[test]
+    fn test_copy_live_into_compacts() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let dest = ZapHeap::<TestHeader>::new();
+        let object_count = 4 * constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let mut live = Vec::new();
+
+        for i in 0..object_count {
+            let ptr = heap.alloc(SmallTestObj { data: i as u32 }).unwrap();
+
+            if i % 4 == 0 {
+                mark(ptr);
+                live.push(ptr);
+            }
+        }
+
+        let mut moved = Vec::new();
+        heap.copy_live_into(&dest, &mut |old, new| moved.push((old, new))).unwrap();
+
+        let source_blocks = unsafe { (*heap.blocks.get()).block_count() };
+        let dest_blocks = unsafe { (*dest.blocks.get()).block_count() };
+
+        assert!(moved.len() == live.len());
+        assert!(dest_blocks < source_blocks);
+
+        for (old, new) in moved {
+            let old_copy = unsafe { &*(old.as_ptr() as *const SmallTestObj) };
+            let new_copy = unsafe { &*(new.as_ptr() as *const SmallTestObj) };
+
+            assert!(new_copy.data == old_copy.data);
+            assert!(new_copy.data % 4 == 0);
+            assert!(!is_marked(new));
+        }
+    }
+
+    #[test]
+    fn test_reset_nursery_keeps_tenured() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let old = heap.alloc_old(SmallTestObj { data: 1 }).unwrap();
+        let young = heap.alloc_young(SmallTestObj { data: 2 }).unwrap();
+        let nursery = unsafe { &*heap.blocks.get() };
+        let tenured = unsafe { &*heap.tenured.get() };
+
+        assert!(nursery.head.as_ref().unwrap().contains(young.as_ptr() as *const u8));
+        assert!(tenured.head.as_ref().unwrap().contains(old.as_ptr() as *const u8));
+
+        heap.reset_nursery();
+
+        let old_copy = unsafe { old.as_ref() };
+
+        assert!(nursery.head.is_none());
+        assert!(nursery.free.len() == 1);
+        assert!(tenured.head.is_some());
+        assert!(tenured.free.is_empty());
+        assert!(old_copy.data == 1);
+        assert!(heap.alloc_young(SmallTestObj { data: 3 }).unwrap() == young);
+    }
+
+    #[test]
+    fn test_mark_from_roots_marks_tenured() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let old = heap.alloc_old(SmallTestObj { data: 1 }).unwrap();
+        let young = heap.alloc_young(SmallTestObj { data: 2 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[old.as_untyped(), young.as_untyped()]) };
+
+        let tenured = unsafe { &*heap.tenured.get() };
+
+        assert!(is_marked(old.as_untyped()));
+        assert!(is_marked(young.as_untyped()));
+        assert!(tenured.head.as_ref().unwrap().is_marked());
+    }
+
+    #[test]
+    fn test_block_index_of() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+
+        for _ in 1..per_block {
+            heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        }
+
+        let second = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        let old = heap.alloc_old(SmallTestObj { data: 4 }).unwrap();
+        let outside = SmallTestObj { data: 5 };
+        let first_index = heap.block_index_of(first.as_untyped()).unwrap();
+        let second_index = heap.block_index_of(second.as_untyped()).unwrap();
+
+        assert!(first_index != second_index);
+        assert!(heap.block_index_of(old.as_untyped()) == Some(2));
+        assert!(heap.block_index_of(NonNull::from(&outside).cast()).is_none());
+
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(blocks.head.as_ref().unwrap().contains(second.as_ptr() as *const u8));
+        assert!(blocks.used[0].contains(first.as_ptr() as *const u8));
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_type_histogram() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        heap.record_type_histogram();
+
+        for _ in 0..3 {
+            heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        }
+
+        heap.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+        heap.alloc_array(16).unwrap();
+        heap.alloc_array(32).unwrap();
+
+        let histogram = heap.type_histogram();
+
+        assert!(histogram[&TestTypeId::Small] == 3);
+        assert!(histogram[&TestTypeId::Medium] == 1);
+        assert!(histogram[&TestTypeId::Array] == 2);
+        assert!(!histogram.contains_key(&TestTypeId::Large));
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_latency_stats_split_fast_and_slow() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        // the first allocation takes the head block, the rest bump it
+        for _ in 0..4 {
+            heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        }
+
+        heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
+
+        let latency = heap.latency_stats();
+
+        assert!(latency.fast_count() == 3);
+        assert!(latency.slow_count() == 2);
+    }
+
+    #[test]
+    fn test_new_typed_sizes_from_type() {
+        let header = TestHeader::new_typed::<MediumTestObj>(SizeClass::Medium, Mark::Allocated);
+        let heap = ZapHeap::<TestHeader>::new();
+        let ptr = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let allocated = unsafe { ZapHeap::<TestHeader>::get_header(ptr.as_untyped()).as_ref() };
+
+        assert!(header.size() as usize == size_of::<MediumTestObj>());
+        assert!(header.type_id() == TestTypeId::Medium);
+        assert!(allocated.size() as usize == size_of::<SmallTestObj>());
+    }
+
+    #[test]
+    fn test_alloc_with_trailer() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let ptr = heap.alloc_with_trailer(SmallTestObj { data: 7 }, 64).unwrap();
+        let header = unsafe { ZapHeap::<TestHeader>::get_header(ptr.as_untyped()).as_ref() };
+        let trailer = unsafe { from_raw_parts_mut(ptr.as_ptr().offset(1) as *mut u8, 64) };
+
+        assert!(header.size() as usize == size_of::<SmallTestObj>() + 64);
+        assert!(trailer.iter().all(|byte| *byte == 0));
+
+        for (i, byte) in trailer.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let next = heap.alloc(SmallTestObj { data: 8 }).unwrap();
+        let trailer = unsafe { from_raw_parts_mut(ptr.as_ptr().offset(1) as *mut u8, 64) };
+
+        assert!(unsafe { ptr.as_ref() }.data == 7);
+        assert!(unsafe { next.as_ref() }.data == 8);
+        assert!(trailer.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+    }
+
+    #[test]
+    fn test_alloc_with_trailer_overflow() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let result = heap.alloc_with_trailer(SmallTestObj { data: 7 }, usize::MAX);
+
+        assert!(result.err().unwrap() == AllocError::BadRequest);
+    }
+
+    #[test]
+    fn test_alloc_or_abort() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let ptr = heap.alloc_or_abort(SmallTestObj { data: 1 });
+        let array = heap.alloc_array_or_abort(16);
+
+        assert!(unsafe { ptr.as_ref() }.data == 1);
+        assert!(array.as_word() != ptr.as_word());
+    }
+
+    #[test]
+    #[should_panic(expected = "BadRequest allocating 4294967295 bytes")]
+    fn test_alloc_array_or_abort_too_big() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc_array_or_abort(ArraySize::MAX);
+    }
+
+    #[test]
+    fn test_inline_bump_fast_path() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        assert!(heap.bump_bounds().is_none());
+
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let (cursor, limit) = heap.bump_bounds().unwrap();
+        let size = alloc_size::<SmallTestObj>();
+        let space = unsafe { cursor.sub(size) };
+
+        assert!(space >= limit);
+
+        let header = TestHeader::new::<SmallTestObj>(4, SizeClass::Small, Mark::Allocated);
+
+        unsafe {
+            write(space as *mut TestHeader, header);
+            write(space.add(8) as *mut SmallTestObj, SmallTestObj { data: 2 });
+            heap.publish_cursor(space);
+        }
+
+        let second = RawPtr::new(unsafe { space.add(8) } as *const SmallTestObj);
+
+        unsafe { heap.mark_from_roots(&[first.as_untyped(), second.as_untyped()]) };
+
+        assert!(is_marked(second.as_untyped()));
+        assert!(heap.bump_bounds().unwrap().0 == space);
+        assert!(unsafe { second.as_ref() }.data == 2);
+    }
+
+    #[test]
+    fn test_array_ptr_bounds() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let slice = heap.alloc_slice(5, 3u64).unwrap();
+        let mut array = unsafe { heap.array_ptr(slice) };
+
+        assert!(array.len() == 5);
+        assert!(unsafe { array.get(4) } == Some(&3));
+        assert!(unsafe { array.get(5) }.is_none());
+        assert!(unsafe { array.set(2, 9) });
+        assert!(!unsafe { array.set(5, 9) });
+        assert!(unsafe { array.get(2) } == Some(&9));
+        assert!(unsafe { array.get(1) } == Some(&3));
+    }
+
+    #[test]
+    fn test_array_ptr_from_alloc_array() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let bytes = heap.alloc_array(10).unwrap();
+        let array = unsafe { heap.array_ptr(bytes) };
+
+        assert!(array.len() == 10);
+        assert!(unsafe { array.get(9) } == Some(&0));
+        assert!(unsafe { array.get(10) }.is_none());
+    }
+
+    #[test]
+    fn test_mark_and_sweep_with_side_metadata() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.set_side_metadata(true);
+
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+
+        for _ in 1..(2 * per_block) {
+            heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        }
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+
+        let blocks = unsafe { &*heap.blocks.get() };
+        let first = &blocks.used[0];
+
+        assert!(first.has_side_metadata());
+        assert!(first.is_marked());
+        assert!(first.is_line_marked(constants::LINE_COUNT - 1));
+        assert!(!first.is_line_marked(0));
+        assert!(!blocks.head.as_ref().unwrap().is_marked());
+
+        heap.sweep();
+
+        assert!(blocks.recycle.len() == 1);
+        assert!(unsafe { live.as_ref() }.data == 1);
+    }
+
+    #[test]
+    fn test_alloc_with_rolls_back_on_panic() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let first = heap.alloc_with(|| SmallTestObj { data: 1 }).unwrap();
+        let (cursor, _) = heap.bump_bounds().unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            heap.alloc_with::<SmallTestObj, _>(|| panic!("construction failed"))
+        }));
+
+        let blocks = unsafe { &*heap.blocks.get() };
+        let head = blocks.head.as_ref().unwrap();
+
+        assert!(result.is_err());
+        assert!(heap.bump_bounds().unwrap().0 == cursor);
+        assert!(head.objects().count() == 1);
+        assert!(blocks.bytes_allocated == alloc_size::<SmallTestObj>());
+
+        let second = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        assert!(second.as_word() + alloc_size::<SmallTestObj>() == first.as_word());
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let per_line = constants::LINE_SIZE / alloc_size::<SmallTestObj>();
+        let mut roots = Vec::new();
+
+        assert!(heap.fragmentation() == 0.0);
+
+        for i in 0..(2 * per_block) {
+            let ptr = heap.alloc(SmallTestObj { data: i as u32 }).unwrap();
+
+            // one object in every fourth line
+            if (i % per_block).is_multiple_of(4 * per_line) {
+                roots.push(ptr.as_untyped());
+            }
+        }
+
+        unsafe { heap.mark_from_roots(&roots) };
+
+        let lines = 2 * constants::LINE_COUNT;
+        let expected = 1.0 - roots.len() as f64 / lines as f64;
+
+        assert!(roots.len() == 2 * constants::LINE_COUNT.div_ceil(4));
+        assert!((heap.fragmentation() - expected).abs() < 1e-9);
+
+        unsafe { heap.mark_from_roots(&[]) };
+
+        assert!(heap.fragmentation() == 1.0);
+    }
+
+    #[test]
+    fn test_alloc_large_objects() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let size = 2 * constants::BLOCK_SIZE;
+        let bytes = heap.alloc_bytes(size, 64, TestTypeId::Large).unwrap();
+        let blocks = unsafe { &*heap.blocks.get() };
+        let header = unsafe { ZapHeap::<TestHeader>::get_header(bytes.as_untyped()).as_ref() };
+
+        assert!(bytes.as_word().is_multiple_of(64));
+        assert!(header.size_class() == SizeClass::Large);
+        assert!(blocks.large.len() == 1);
+        assert!(blocks.head.is_none());
+
+        unsafe { from_raw_parts_mut(bytes.as_ptr() as *mut u8, size).fill(7) };
+    }
+
+    #[test]
+    fn test_sweep_frees_dead_large_objects() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc_array(constants::BLOCK_SIZE as ArraySize).unwrap();
+
+        heap.alloc_array(constants::BLOCK_SIZE as ArraySize).unwrap();
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.sweep();
+
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(blocks.large.len() == 1);
+        assert!(blocks.large[0].header == ZapHeap::<TestHeader>::get_header(live.as_untyped()).as_ptr() as *const u8);
+    }
+
+    #[test]
+    fn test_take_large() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let first = heap.alloc_array(constants::BLOCK_SIZE as ArraySize).unwrap();
+        let second = heap.alloc_array(2 * constants::BLOCK_SIZE as ArraySize).unwrap();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        let taken = heap.take_large();
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(taken.len() == 2);
+        assert!(blocks.large.is_empty());
+        assert!(taken[0].0 == ZapHeap::<TestHeader>::get_header(first.as_untyped()));
+        assert!(taken[0].1 == header_alloc_size + constants::BLOCK_SIZE);
+        assert!(taken[1].0 == ZapHeap::<TestHeader>::get_header(second.as_untyped()));
+        assert!(taken[1].1 == header_alloc_size + 2 * constants::BLOCK_SIZE);
+
+        for (header, _) in taken {
+            heap.free_large(header);
+        }
+
+        assert!(blocks.taken_large.is_empty());
+        assert!(heap.take_large().is_empty());
+    }
+
+    #[test]
+    fn test_free_large_clears_weak_refs() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let large = heap.alloc_array(constants::BLOCK_SIZE as ArraySize).unwrap();
+        let weak = heap.new_weak(large);
+
+        heap.free_large(ZapHeap::<TestHeader>::get_header(large.as_untyped()));
+        heap.sweep();
+
+        assert!(weak.upgrade().is_none());
+        assert!(unsafe { &*heap.weak_refs.get() }.is_empty());
+    }
+
+    #[cfg(feature = "debug-alloc-sites")]
+    #[test]
+    fn test_alloc_sites() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let second = heap.alloc_array(16).unwrap();
+        let first_site = heap.alloc_site(first.as_untyped()).unwrap();
+        let second_site = heap.alloc_site(second.as_untyped()).unwrap();
+
+        assert!(first_site.file() == file!());
+        assert!(first_site.line() + 1 == second_site.line());
+
+        unsafe { heap.mark_from_roots(&[first.as_untyped()]) };
+        heap.sweep();
+
+        assert!(heap.alloc_site(first.as_untyped()).is_some());
+        assert!(heap.alloc_site(second.as_untyped()).is_none());
+    }
+
+    // 12 bytes, so it pads out to 16 in front of every payload
+    struct WideHeader {
+        inner: TestHeader,
+        tag: u32,
+    }
+
+    impl AllocHeader for WideHeader {
+        type TypeId = TestTypeId;
+
+        fn new<O: AllocObject<Self::TypeId>>(size: u32, size_class: SizeClass, mark: Mark) -> Self {
+            WideHeader { inner: TestHeader::new::<O>(size, size_class, mark), tag: 0xabcd }
+        }
 
         fn new_array(size: u32, size_class: SizeClass, mark: Mark) -> Self {
-            TestHeader {
+            WideHeader { inner: TestHeader::new_array(size, size_class, mark), tag: 0xabcd }
+        }
+
+        fn new_bytes(type_id: TestTypeId, size: u32, size_class: SizeClass, mark: Mark) -> Self {
+            WideHeader { inner: TestHeader::new_bytes(type_id, size, size_class, mark), tag: 0xabcd }
+        }
+
+        fn mark(&mut self) {
+            self.inner.mark();
+        }
+
+        fn unmark(&mut self) {
+            self.inner.unmark();
+        }
+
+        fn mark_freed(&mut self) {
+            self.inner.mark_freed();
+        }
+
+        fn mark_forwarded(&mut self) {
+            self.inner.mark_forwarded();
+        }
+
+        fn reset_mark(&mut self) {
+            self.inner.reset_mark();
+        }
+
+        fn is_marked(&self) -> bool {
+            self.inner.is_marked()
+        }
+
+        fn mark_state(&self) -> Mark {
+            self.inner.mark_state()
+        }
+
+        fn pin(&mut self) {
+            self.inner.pin();
+        }
+
+        fn unpin(&mut self) {
+            self.inner.unpin();
+        }
+
+        fn is_pinned(&self) -> bool {
+            self.inner.is_pinned()
+        }
+
+        fn type_id(&self) -> Self::TypeId {
+            self.inner.type_id()
+        }
+
+        fn size(&self) -> u32 {
+            self.inner.size()
+        }
+
+        fn size_class(&self) -> SizeClass {
+            self.inner.size_class()
+        }
+    }
+
+    #[test]
+    fn test_header_round_trip_with_unpadded_header() {
+        let heap = ZapHeap::<WideHeader>::new();
+        let object = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        let array = heap.alloc_array(24).unwrap();
+
+        assert!(size_of::<WideHeader>() == 12);
+
+        for (ptr, size) in [(object.as_untyped(), size_of::<SmallTestObj>()), (array.as_untyped(), 24)] {
+            let header_ptr = ZapHeap::<WideHeader>::get_header(ptr);
+            let header = unsafe { header_ptr.as_ref() };
+
+            assert!(header.tag == 0xabcd);
+            assert!(header.size() as usize == size);
+            assert!(header_ptr.as_ptr() as usize + 16 == ptr.as_ptr() as usize);
+            assert!(ZapHeap::<WideHeader>::get_object(header_ptr) == ptr);
+        }
+
+        assert!(unsafe { object.as_ref() }.data == 3);
+    }
+
+    #[test]
+    fn test_collect_and_copy_keep_header_fields() {
+        let heap = ZapHeap::<WideHeader>::new();
+        let dest = ZapHeap::<WideHeader>::new();
+        let object = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        let header = |ptr: NonNull<()>| unsafe { &mut *ZapHeap::<WideHeader>::get_header(ptr).as_ptr() };
+
+        header(object.as_untyped()).tag = 7;
+
+        unsafe { heap.mark_from_roots(&[object.as_untyped()]) };
+        heap.collect();
+
+        assert!(header(object.as_untyped()).tag == 7);
+        assert!(header(object.as_untyped()).mark_state() == Mark::Allocated);
+
+        let mut moved = Vec::new();
+
+        unsafe { heap.mark_from_roots(&[object.as_untyped()]) };
+        heap.copy_live_into(&dest, &mut |_, new| moved.push(new)).unwrap();
+
+        assert!(header(moved[0]).tag == 7);
+        assert!(header(moved[0]).mark_state() == Mark::Allocated);
+    }
+
+    #[test]
+    fn test_deep_copy_is_independent() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let large = heap.alloc_array(constants::BLOCK_SIZE as ArraySize).unwrap();
+
+        unsafe { heap.mark_from_roots(&[first.as_untyped(), large.as_untyped()]) };
+
+        let (copy, remap) = heap.deep_copy().unwrap();
+        let first_copy = remap(first.as_untyped()).cast::<SmallTestObj>();
+        let large_copy = remap(large.as_untyped()).cast::<u8>();
+
+        assert!(first_copy != first.as_untyped().cast());
+        assert!(remap(dead.as_untyped()) == dead.as_untyped());
+        assert!(unsafe { (*copy.blocks.get()).large.len() } == 1);
+
+        unsafe {
+            (*first_copy.as_ptr()).data = 10;
+            *large_copy.as_ptr() = 0xff;
+        }
+
+        assert!(unsafe { first.as_ref() }.data == 1);
+        assert!(unsafe { *large.as_ptr() } == 0);
+        assert!(unsafe { first_copy.as_ref() }.data == 10);
+    }
+
+    #[test]
+    fn test_sweep_ignores_last_generation_marks() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let old = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        for _ in 1..(2 * per_block) {
+            heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        }
+
+        unsafe { heap.mark_from_roots(&[old.as_untyped()]) };
+
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(blocks.used[0].is_marked());
+
+        unsafe { heap.mark_from_roots(&[]) };
+
+        assert!(!blocks.used[0].is_marked());
+        assert!(!blocks.used[0].has_marked_lines());
+
+        heap.sweep();
+
+        assert!(blocks.free.len() == 1);
+    }
+
+    #[test]
+    fn test_mark_generation_wraps() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        unsafe { heap.mark_from_roots(&[dead.as_untyped()]) };
+
+        for _ in 0..u8::MAX {
+            unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        }
+
+        let head = blocks.head.as_ref().unwrap();
+        let dead_line = head.offset_of(dead.as_ptr() as *const u8) / constants::LINE_SIZE;
+        let live_line = head.offset_of(live.as_ptr() as *const u8) / constants::LINE_SIZE;
+
+        assert!(blocks.mark_generation == 2);
+        assert!(head.occupied_lines() == 1);
+        assert!(head.is_line_marked(live_line));
+        assert!(dead_line == live_line || !head.is_line_marked(dead_line));
+    }
+
+    #[test]
+    fn test_with_prealloc() {
+        let heap = ZapHeap::<TestHeader>::with_prealloc(4).unwrap();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let block_sized = constants::BLOCK_CAPACITY - header_alloc_size;
+
+        assert!(heap.block_count() == 4);
+
+        for _ in 0..4 {
+            heap.alloc_bytes(block_sized, 8, TestTypeId::Medium).unwrap();
+
+            assert!(heap.block_count() == 4);
+        }
+
+        heap.alloc_bytes(block_sized, 8, TestTypeId::Medium).unwrap();
+
+        assert!(heap.block_count() == 5);
+    }
+
+    #[test]
+    fn test_verify_clean_heap() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        heap.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+        heap.alloc_bytes(20000, 8, TestTypeId::Large).unwrap();
+        heap.alloc_slice(100, 1u16).unwrap();
+
+        assert!(heap.verify() == Ok(()));
+
+        unsafe { heap.mark_from_roots(&[small.as_untyped()]) };
+
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_verify_detects_corruption() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let object = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let mut header_ptr = ZapHeap::<TestHeader>::get_header(object.as_untyped());
+        let header = unsafe { header_ptr.as_mut() };
+
+        header.size = 4000;
+
+        let result = heap.verify();
+
+        assert!(result == Err(HeapError::BadSizeClass(header_ptr.as_ptr() as *const u8)));
+
+        header.size = size_of::<SmallTestObj>() as u32;
+        header.mark();
+
+        assert!(heap.verify() == Err(HeapError::UnmarkedLine(header_ptr.as_ptr() as *const u8)));
+    }
+
+    #[test]
+    fn test_alloc_line_aligned() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        for i in 0..3 {
+            let ptr = heap.alloc_line_aligned(SmallTestObj { data: i }).unwrap();
+            let header = ZapHeap::<TestHeader>::get_header(ptr.as_untyped()).as_ptr() as *const u8;
+            let offset = blocks.head.as_ref().unwrap().offset_of(header);
+
+            assert!(offset.is_multiple_of(constants::LINE_SIZE));
+            assert!(unsafe { ptr.as_ref() }.data == i);
+        }
+
+        let medium = heap.alloc_line_aligned(MediumTestObj { data: [1; 256] }).unwrap();
+        let header = ZapHeap::<TestHeader>::get_header(medium.as_untyped()).as_ptr() as *const u8;
+
+        assert!(blocks.head.as_ref().unwrap().offset_of(header).is_multiple_of(constants::LINE_SIZE));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_holes_follow_line_marks() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let old = heap.alloc_old(SmallTestObj { data: 1 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        unsafe { heap.mark_from_roots(&[old.as_untyped()]) };
+
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let head = blocks.head.as_mut().unwrap();
+
+        head.mark_line(10);
+        head.mark_line(11);
+        head.mark_line(50);
+
+        let line = constants::LINE_SIZE;
+        let holes: Vec<_> = heap.holes().collect();
+        let old_line = constants::LINE_COUNT - 1;
+
+        assert!(holes[0] == (0, 0, 10 * line));
+        assert!(holes[1] == (0, 13 * line, 37 * line));
+        assert!(holes[2] == (0, 52 * line, (constants::LINE_COUNT - 52) * line));
+        assert!(holes[3] == (1, 0, old_line * line));
+        assert!(holes.len() == 4);
+    }
+
+    #[test]
+    fn test_alloc_near_hint() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+
+        for _ in 0..(per_block - 1) {
+            heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        }
+
+        // a medium object that misses the head lands in the overflow block
+        let parent = heap.alloc_bytes(constants::LINE_SIZE * 4, 8, TestTypeId::Medium).unwrap().as_untyped();
+        let elsewhere = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        let child = heap.alloc_near(SmallTestObj { data: 4 }, parent).unwrap();
+        let parent_block = heap.block_index_of(parent);
+
+        assert!(heap.block_index_of(elsewhere.as_untyped()) != parent_block);
+        assert!(heap.block_index_of(child.as_untyped()) == parent_block);
+        assert!(unsafe { child.as_ref() }.data == 4);
+
+        let old = heap.alloc_old(SmallTestObj { data: 5 }).unwrap();
+        let old_child = heap.alloc_near(SmallTestObj { data: 6 }, old.as_untyped()).unwrap();
+
+        assert!(heap.block_index_of(old_child.as_untyped()) == heap.block_index_of(old.as_untyped()));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_mark_object_keeps_only_marked() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let objects: Vec<_> = (0..(per_block * 2)).map(|i| heap.alloc(SmallTestObj { data: i as u32 }).unwrap()).collect();
+
+        unsafe { heap.mark_from_roots(&[]) };
+
+        for object in objects.iter().step_by(50) {
+            unsafe { heap.mark_object(object.as_untyped()) };
+        }
+
+        heap.sweep();
+
+        for (i, object) in objects.iter().enumerate() {
+            assert!(heap.is_live(object.as_untyped()) == (i % 50 == 0));
+        }
+
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_reserve_keeps_burst_in_one_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let count = per_block / 2;
+        let first = heap.alloc(SmallTestObj { data: 0 }).unwrap();
+
+        for data in 1..(per_block - 1) {
+            heap.alloc(SmallTestObj { data: data as u32 }).unwrap();
+        }
+
+        heap.reserve(count * alloc_size::<SmallTestObj>()).unwrap();
+
+        let refills = heap.stats().total_refills;
+        let burst: Vec<_> = (0..count).map(|_| heap.alloc(SmallTestObj { data: 1 }).unwrap()).collect();
+        let block = heap.block_index_of(burst[0].as_untyped());
+
+        assert!(block != heap.block_index_of(first.as_untyped()));
+        assert!(burst.iter().all(|object| heap.block_index_of(object.as_untyped()) == block));
+        assert!(heap.stats().total_refills == refills);
+
+        heap.reserve(8).unwrap();
+
+        assert!(heap.stats().total_refills == refills);
+        assert!(heap.reserve(constants::BLOCK_CAPACITY + 1).err() == Some(AllocError::BadRequest));
+    }
+
+    #[test]
+    fn test_reserve_uses_existing_holes() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &*heap.blocks.get() };
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let bytes = 8 * alloc_size::<SmallTestObj>();
+        let objects: Vec<_> = (0..(2 * per_block - 1)).map(|data| heap.alloc(SmallTestObj { data: data as u32 }).unwrap()).collect();
+        let roots = [objects[0], objects[per_block / 2], objects[per_block - 1]];
+
+        unsafe { heap.mark_from_roots(&roots.map(|root| root.as_untyped())) };
+        heap.sweep();
+
+        let block_count = heap.block_count();
+        let refills = heap.stats().total_refills;
+
+        assert!(blocks.head.as_ref().unwrap().max_hole() < bytes);
+
+        heap.reserve(bytes).unwrap();
+
+        let head = blocks.head.as_ref().unwrap();
+
+        assert!(head.contains(objects[0].as_ptr() as *const u8));
+        assert!(heap.block_count() == block_count);
+        assert!(heap.stats().total_refills == refills + 1);
+
+        // use up the hole being bumped, leaving the other one below it
+        while blocks.head.as_ref().unwrap().current_hole_size() >= bytes {
+            heap.alloc(SmallTestObj { data: 0 }).unwrap();
+        }
+
+        heap.reserve(bytes).unwrap();
+
+        assert!(blocks.head.as_ref().unwrap().contains(objects[0].as_ptr() as *const u8));
+        assert!(heap.stats().total_refills == refills + 1);
+    }
+
+    #[test]
+    fn test_recycle_head_moves_allocation_on() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &*heap.blocks.get() };
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        heap.recycle_head();
+
+        assert!(blocks.head.is_none());
+        assert!(blocks.recycle.len() == 1);
+
+        let second = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        assert!(heap.block_index_of(second.as_untyped()) != heap.block_index_of(first.as_untyped()));
+
+        // the retired head picks up below the object it already holds
+        let recycled = blocks.recycle.iter().next().unwrap().base();
+        let third = heap.alloc_into(SmallTestObj { data: 3 }, BlockTarget::Recycle(recycled)).unwrap();
+
+        assert!(third.as_word() < first.as_word());
+        assert!(unsafe { first.as_ref() }.data == 1);
+    }
+
+    #[test]
+    fn test_medium_alloc_pops_block_with_room() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &*heap.blocks.get() };
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let per_line = constants::LINE_SIZE / alloc_size::<SmallTestObj>();
+        let objects: Vec<_> = (0..(per_block * 2 + 1)).map(|_| heap.alloc(SmallTestObj { data: 1 }).unwrap()).collect();
+
+        // a live object every third line leaves the first block one line
+        // holes, while the second keeps nearly all of its lines free
+        let mut roots: Vec<_> = objects[..per_block].iter().step_by(per_line * 3).map(|object| object.as_untyped()).collect();
+        let roomy = objects[per_block].as_untyped();
+
+        roots.push(roomy);
+        unsafe { heap.mark_from_roots(&roots) };
+        heap.sweep();
+
+        assert!(blocks.recycle.small.len() == 1);
+        assert!(blocks.recycle.medium.len() == 1);
+
+        let cramped = blocks.recycle.small[0].base();
+        let medium = heap.alloc(MediumTestObj { data: [9; 256] }).unwrap();
+
+        assert!(heap.block_index_of(medium.as_untyped()) == heap.block_index_of(roomy));
+        assert!(blocks.overflow.as_ref().unwrap().contains(roomy.as_ptr() as *const u8));
+        assert!(blocks.recycle.medium.is_empty());
+        assert!(blocks.recycle.small[0].base() == cramped);
+        assert!(blocks.recycle.small[0].current_hole_size() == 0);
+    }
+
+    #[test]
+    fn test_alloc_into_recycle_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let survivor = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        for _ in 0..(per_block * 2) {
+            heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        }
+
+        unsafe { heap.mark_from_roots(&[survivor.as_untyped()]) };
+        heap.sweep();
+
+        let recycled = heap.dump_layout().iter().find(|layout| layout.role == BlockRole::Recycle).unwrap().base;
+        let in_head = heap.alloc_into(SmallTestObj { data: 3 }, BlockTarget::Head).unwrap();
+        let in_recycle = heap.alloc_into(SmallTestObj { data: 4 }, BlockTarget::Recycle(recycled)).unwrap();
+
+        assert!(heap.block_index_of(in_recycle.as_untyped()) == heap.block_index_of(survivor.as_untyped()));
+        assert!(heap.block_index_of(in_head.as_untyped()) != heap.block_index_of(in_recycle.as_untyped()));
+        assert!(unsafe { in_recycle.as_ref() }.data == 4);
+
+        let missing = heap.alloc_into(SmallTestObj { data: 5 }, BlockTarget::Overflow);
+
+        assert!(missing.err() == Some(AllocError::BadRequest));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    fn block_layout(heap: &ZapHeap<TestHeader>) -> Vec<(Option<usize>, usize)> {
+        let blocks = unsafe { &*heap.blocks.get() };
+        let tenured = unsafe { &*heap.tenured.get() };
+
+        blocks
+            .headers()
+            .chain(tenured.headers())
+            .map(|header| {
+                let object = unsafe { NonNull::new_unchecked(header as *mut ()) };
+
+                (heap.block_index_of(object), header as usize % constants::BLOCK_SIZE)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_alloc_log() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut seed: u32 = 7;
+
+        heap.record_allocs();
+
+        for _ in 0..2000 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+
+            let roll = (seed >> 16) as usize;
+
+            match roll % 4 {
+                0 => {
+                    heap.alloc(SmallTestObj { data: roll as u32 }).unwrap();
+                }
+                1 => {
+                    heap.alloc_array((roll % 2000) as ArraySize).unwrap();
+                }
+                2 => {
+                    heap.alloc_bytes(roll % 300, 1 << (roll % 7), TestTypeId::Medium).unwrap();
+                }
+                _ => {
+                    heap.alloc_old(SmallTestObj { data: roll as u32 }).unwrap();
+                }
+            }
+        }
+
+        let log = heap.alloc_log();
+
+        assert!(log.len() == 2000);
+
+        let words: Vec<usize> = log
+            .iter()
+            .flat_map(|event| {
+                let type_id = match event.type_id {
+                    TestTypeId::Small => 0,
+                    TestTypeId::Medium => 1,
+                    TestTypeId::Large => 2,
+                    TestTypeId::Array => 3,
+                };
+
+                [event.op as usize, event.size, event.align, event.aligned_offset, type_id]
+            })
+            .collect();
+        let parsed: Vec<AllocEvent<TestTypeId>> = words
+            .chunks(5)
+            .map(|word| AllocEvent {
+                op: if word[0] == AllocOp::Old as usize { AllocOp::Old } else { AllocOp::Young },
+                size: word[1],
+                align: word[2],
+                aligned_offset: word[3],
+                type_id: [TestTypeId::Small, TestTypeId::Medium, TestTypeId::Large, TestTypeId::Array][word[4]],
+            })
+            .collect();
+
+        assert!(parsed == log);
+
+        let replayed = ZapHeap::<TestHeader>::replay(&parsed).unwrap();
+
+        assert!(replayed.block_count() == heap.block_count());
+        assert!(block_layout(&replayed) == block_layout(&heap));
+        assert!(replayed.alloc_log() == log);
+    }
+
+    #[test]
+    fn test_alloc_batch() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let objects = heap.alloc_batch(SmallTestObj { data: 42 }, 1000).unwrap();
+        let mut addresses: Vec<usize> = objects.iter().map(|object| object.as_word()).collect();
+
+        addresses.sort();
+        addresses.dedup();
+
+        assert!(objects.len() == 1000);
+        assert!(addresses.len() == 1000);
+
+        for object in &objects {
+            let header = unsafe { ZapHeap::<TestHeader>::get_header(object.as_untyped()).as_ref() };
+
+            assert!(unsafe { object.as_ref() }.data == 42);
+            assert!(header.type_id() == TestTypeId::Small);
+        }
+
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_alloc_batch_too_big() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        assert!(heap.alloc_batch(SmallTestObj { data: 42 }, usize::MAX).err() == Some(AllocError::BadRequest));
+        assert!(heap.block_count() == 0);
+    }
+
+    #[test]
+    fn test_weak_ref_cleared_by_sweep() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let live_weak = heap.new_weak(live);
+        let dead_weak = heap.new_weak(dead);
+        let dead_weak_copy = dead_weak.clone();
+
+        assert!(dead_weak.upgrade() == Some(dead));
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.sweep();
+
+        assert!(live_weak.upgrade() == Some(live));
+        assert!(dead_weak.upgrade().is_none());
+        assert!(dead_weak_copy.upgrade().is_none());
+
+        heap.reset_nursery();
+
+        assert!(live_weak.upgrade().is_none());
+    }
+
+    // hands out blocks from the global allocator until the `fail_at`th
+    struct FailingSource {
+        allocs: AtomicUsize,
+        fail_at: usize,
+    }
+
+    impl BlockSource for FailingSource {
+        fn alloc(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+            if self.allocs.fetch_add(1, Ordering::Relaxed) + 1 == self.fail_at {
+                return None;
+            }
+
+            NonNull::new(unsafe { std::alloc::alloc(std::alloc::Layout::from_size_align(size, align).unwrap()) })
+        }
+
+        unsafe fn dealloc(&self, ptr: NonNull<u8>, size: usize, align: usize) {
+            std::alloc::dealloc(ptr.as_ptr(), std::alloc::Layout::from_size_align_unchecked(size, align));
+        }
+    }
+
+    #[test]
+    fn test_block_source_oom() {
+        let source = Arc::new(FailingSource { allocs: AtomicUsize::new(0), fail_at: 3 });
+        let heap = ZapHeap::<TestHeader>::with_source(source.clone());
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+
+        for _ in 0..(per_block * 2) {
+            heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        }
+
+        let mut result = heap.alloc(SmallTestObj { data: 2 });
+
+        while result.is_ok() {
+            result = heap.alloc(SmallTestObj { data: 2 });
+        }
+
+        assert!(result.err() == Some(AllocError::OOM));
+        assert!(source.allocs.load(Ordering::Relaxed) == 3);
+        assert!(heap.block_count() == 2);
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_consolidate_recycle() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let mut by_block: HashMap<usize, Vec<RawPtr<SmallTestObj>>> = HashMap::new();
+
+        for i in 0..(per_block * 3 + 1) {
+            let object = heap.alloc(SmallTestObj { data: i as u32 }).unwrap();
+
+            by_block.entry(object.as_word() & !(constants::BLOCK_SIZE - 1)).or_default().push(object);
+        }
+
+        // the first third of each full block stays live
+        let roots: Vec<RawPtr<SmallTestObj>> = by_block
+            .values()
+            .filter(|objects| objects.len() == per_block)
+            .flat_map(|objects| objects[..per_block / 3].iter().copied())
+            .collect();
+        let untyped: Vec<NonNull<()>> = roots.iter().map(|root| root.as_untyped()).collect();
+        let expected: Vec<u32> = roots.iter().map(|root| unsafe { root.as_ref() }.data).collect();
+
+        unsafe { heap.mark_from_roots(&untyped) };
+        heap.sweep();
+
+        assert!(blocks.recycle.len() == 3);
+
+        let mut moved = HashMap::new();
+
+        heap.consolidate_recycle(&mut |old, new| {
+            moved.insert(old, new);
+        });
+
+        assert!(!moved.is_empty());
+        assert!(blocks.recycle.len() <= 2);
+        assert!(blocks.free.len() == 3 - blocks.recycle.len());
+
+        for (root, data) in untyped.iter().zip(expected) {
+            let object = moved.get(root).copied().unwrap_or(*root);
+
+            assert!(heap.block_index_of(object).is_some());
+            assert!(unsafe { &*(object.as_ptr() as *const SmallTestObj) }.data == data);
+        }
+    }
+
+    #[test]
+    fn test_consolidate_recycle_keeps_new_objects() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+
+        for i in 0..64 {
+            mark(heap.alloc(SmallTestObj { data: i }).unwrap());
+        }
+
+        blocks.recycle.push(blocks.head.take().unwrap());
+        heap.alloc(SmallTestObj { data: 64 }).unwrap();
+
+        // allocated since the last mark, so never marked
+        let aligned = heap.alloc(AlignedTestObj { data: 65 }).unwrap();
+
+        blocks.recycle.push(blocks.head.take().unwrap());
+
+        let mut moved = Vec::new();
+
+        heap.consolidate_recycle(&mut |old, new| moved.push((old, new)));
+
+        let (_, new) = moved.iter().find(|(old, _)| *old == aligned.as_untyped()).unwrap();
+
+        assert!((new.as_ptr() as usize).is_multiple_of(64));
+        assert!(unsafe { &*(new.as_ptr() as *const AlignedTestObj) }.data == 65);
+    }
+
+    #[test]
+    fn test_alloc_array_uninit_skips_zeroing() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let size = 1024;
+        let dirty = heap.alloc_array(size as ArraySize).unwrap();
+
+        unsafe { dirty.as_ptr().cast_mut().write_bytes(0xab, size) };
+
+        // the freed block comes straight back, so the next array lands on the
+        // dirty bytes
+        heap.reset_nursery();
+
+        let array = heap.alloc_array_uninit(size as ArraySize).unwrap();
+        let bytes = unsafe { from_raw_parts_mut(array.as_ptr().cast_mut(), size) };
+
+        assert!(array.as_word() == dirty.as_word());
+        assert!(bytes.iter().all(|byte| unsafe { byte.assume_init() } == 0xab));
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            byte.write(i as u8);
+        }
+
+        assert!(bytes.iter().enumerate().all(|(i, byte)| unsafe { byte.assume_init() } == i as u8));
+
+        heap.reset_nursery();
+
+        let zeroed = heap.alloc_array(size as ArraySize).unwrap();
+        let zeroed_bytes = unsafe { std::slice::from_raw_parts(zeroed.as_ptr(), size) };
+
+        assert!(zeroed.as_word() == dirty.as_word());
+        assert!(zeroed_bytes.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_reset_to_checkpoint() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let kept: Vec<RawPtr<SmallTestObj>> =
+            (0..10).map(|i| heap.alloc(SmallTestObj { data: i }).unwrap()).collect();
+        let kept_old = heap.alloc_old(SmallTestObj { data: 100 }).unwrap();
+        let in_use = |heap: &ZapHeap<TestHeader>| unsafe {
+            (*heap.blocks.get()).iter().count() + (*heap.tenured.get()).iter().count()
+        };
+        let block_count = in_use(&heap);
+        let checkpoint = heap.checkpoint();
+        let first = heap.alloc(SmallTestObj { data: 200 }).unwrap();
+        let weak = heap.new_weak(first);
+
+        for _ in 0..(per_block * 2) {
+            heap.alloc(SmallTestObj { data: 300 }).unwrap();
+        }
+
+        heap.alloc_old(SmallTestObj { data: 400 }).unwrap();
+        heap.alloc_bytes(constants::BLOCK_SIZE * 2, 8, TestTypeId::Large).unwrap();
+
+        assert!(in_use(&heap) > block_count);
+
+        heap.reset_to(checkpoint).unwrap();
+
+        let headers = unsafe { (*heap.blocks.get()).headers().count() + (*heap.tenured.get()).headers().count() };
+
+        assert!(in_use(&heap) == block_count);
+        assert!(headers == kept.len() + 1);
+        assert!(weak.upgrade().is_none());
+        assert!(unsafe { kept_old.as_ref() }.data == 100);
+
+        for (i, object) in kept.iter().enumerate() {
+            assert!(unsafe { object.as_ref() }.data == i as u32);
+        }
+
+        assert!(heap.alloc(SmallTestObj { data: 500 }).unwrap() == first);
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_layout_accessors() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        let (cursor, limit) = heap.bump_bounds().unwrap();
+
+        let used = ZapHeap::<TestHeader>::block_capacity() - (cursor as usize - limit as usize);
+
+        assert!(used == alloc_size::<SmallTestObj>());
+
+        let aligned = heap.alloc_line_aligned(SmallTestObj { data: 2 }).unwrap();
+        let header = ZapHeap::<TestHeader>::get_header(aligned.as_untyped());
+
+        assert!((header.as_ptr() as usize).is_multiple_of(heap.line_size()));
+    }
+
+    #[test]
+    fn test_relocate() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let object = heap.alloc(SmallTestObj { data: 77 }).unwrap();
+        let weak = heap.new_weak(object);
+        let moved = unsafe { heap.relocate(object) }.unwrap();
+        let old_header = unsafe { ZapHeap::<TestHeader>::get_header(object.as_untyped()).as_ref() };
+        let new_header = unsafe { ZapHeap::<TestHeader>::get_header(moved.as_untyped()).as_ref() };
+
+        assert!(moved != object);
+        assert!(unsafe { moved.as_ref() }.data == 77);
+        assert!(unsafe { object.as_ref() }.data == 77);
+        assert!(new_header.size() == old_header.size());
+        assert!(new_header.type_id() == old_header.type_id());
+        assert!(weak.upgrade() == Some(moved));
+        assert!(unsafe { &*heap.tenured.get() }.owns(moved.as_ptr() as *const u8));
+
+        unsafe { heap.mark_from_roots(&[moved.as_untyped()]) };
+        heap.sweep();
+
+        assert!(weak.upgrade() == Some(moved));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_relocate_refuses_pinned() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let object = heap.alloc(SmallTestObj { data: 77 }).unwrap();
+
+        unsafe { heap.pin(object) };
+
+        assert!(unsafe { heap.relocate(object) } == Err(AllocError::BadRequest));
+        assert!(unsafe { &*heap.blocks.get() }.owns(object.as_ptr() as *const u8));
+        assert!(heap.is_live(object.as_untyped()));
+    }
+
+    #[test]
+    fn test_object_base_from_interior() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let small = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let medium = heap.alloc_bytes(constants::LINE_SIZE * 3, 8, TestTypeId::Medium).unwrap();
+        let array = heap.alloc_array(500).unwrap();
+        let large = heap.alloc_bytes(constants::BLOCK_SIZE * 2, 8, TestTypeId::Large).unwrap();
+        let interior = |object: RawPtr<u8>, offset: usize| unsafe {
+            NonNull::new_unchecked(object.as_ptr().add(offset) as *mut ())
+        };
+        let header_of = |object: RawPtr<u8>| Some(ZapHeap::<TestHeader>::get_header(object.as_untyped()));
+
+        assert!(heap.object_base(interior(medium, constants::LINE_SIZE + 5)) == header_of(medium));
+        assert!(heap.object_base(interior(medium, 0)) == header_of(medium));
+        assert!(heap.object_base(interior(array, 250)) == header_of(array));
+        assert!(heap.object_base(interior(array, 499)) == header_of(array));
+        assert!(heap.object_base(interior(large, constants::BLOCK_SIZE)) == header_of(large));
+        assert!(heap.object_base(small.as_untyped()) == Some(ZapHeap::<TestHeader>::get_header(small.as_untyped())));
+
+        // just below the last object is the free part of the bump hole
+        let (cursor, _) = heap.bump_bounds().unwrap();
+        let free = unsafe { NonNull::new_unchecked(cursor.sub(8) as *mut ()) };
+
+        assert!(heap.object_base(free).is_none());
+    }
+
+    #[test]
+    fn test_conservative_line_marking() {
+        for conservative in [0, 1] {
+            let heap = ZapHeap::<TestHeader>::new();
+
+            heap.set_conservative_lines(conservative);
+
+            // fill the top line so the next object ends right at its start
+            for _ in 0..(constants::LINE_SIZE / alloc_size::<SmallTestObj>()) {
+                heap.alloc(SmallTestObj { data: 1 }).unwrap();
+            }
+
+            let object = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+            let header = ZapHeap::<TestHeader>::get_header(object.as_untyped());
+            let object_end = header.as_ptr() as usize + alloc_size::<SmallTestObj>();
+
+            assert!(object_end.is_multiple_of(constants::LINE_SIZE));
+
+            unsafe { heap.mark_from_roots(&[object.as_untyped()]) };
+
+            let blocks = unsafe { &*heap.blocks.get() };
+
+            assert!(blocks.head.as_ref().unwrap().occupied_lines() == 1 + conservative);
+        }
+    }
+
+    // a fixed arena standing in for the memory an embedded target hands over
+    #[repr(align(16384))]
+    struct Arena(UnsafeCell<[u8; constants::BLOCK_SIZE * 4]>);
+
+    unsafe impl Sync for Arena {}
+
+    static ARENA: Arena = Arena(UnsafeCell::new([0; constants::BLOCK_SIZE * 4]));
+
+    struct StaticSource {
+        next: AtomicUsize,
+    }
+
+    impl BlockSource for StaticSource {
+        fn alloc(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+            if size != constants::BLOCK_SIZE || align != constants::BLOCK_SIZE {
+                return None;
+            }
+
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+
+            if index >= 4 {
+                return None;
+            }
+
+            NonNull::new(unsafe { (ARENA.0.get() as *mut u8).add(index * constants::BLOCK_SIZE) })
+        }
+
+        unsafe fn dealloc(&self, _ptr: NonNull<u8>, _size: usize, _align: usize) {}
+    }
+
+    #[test]
+    fn test_static_block_source() {
+        let heap = ZapHeap::<TestHeader>::with_source(Arc::new(StaticSource { next: AtomicUsize::new(0) }));
+        let arena = ARENA.0.get() as usize..ARENA.0.get() as usize + constants::BLOCK_SIZE * 4;
+        let objects: Vec<RawPtr<SmallTestObj>> =
+            (0..100).map(|i| heap.alloc(SmallTestObj { data: i }).unwrap()).collect();
+        let old = heap.alloc_old(SmallTestObj { data: 100 }).unwrap();
+
+        assert!(arena.contains(&old.as_word()));
+
+        for (i, object) in objects.iter().enumerate() {
+            assert!(arena.contains(&object.as_word()));
+            assert!(unsafe { object.as_ref() }.data == i as u32);
+        }
+
+        assert!(heap.alloc_bytes(constants::BLOCK_SIZE * 2, 8, TestTypeId::Large).err() == Some(AllocError::OOM));
+    }
+
+    #[test]
+    fn test_is_live_after_sweep() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let large_live = heap.alloc_bytes(constants::BLOCK_SIZE * 2, 8, TestTypeId::Large).unwrap();
+        let large_dead = heap.alloc_bytes(constants::BLOCK_SIZE * 2, 8, TestTypeId::Large).unwrap();
+
+        assert!(heap.is_live(live.as_untyped()));
+        assert!(heap.is_live(dead.as_untyped()));
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped(), large_live.as_untyped()]) };
+        heap.sweep();
+
+        let fresh = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        assert!(heap.is_live(live.as_untyped()));
+        assert!(!heap.is_live(dead.as_untyped()));
+        assert!(heap.is_live(large_live.as_untyped()));
+        assert!(!heap.is_live(large_dead.as_untyped()));
+        assert!(heap.is_live(fresh.as_untyped()));
+    }
+
+    #[test]
+    fn test_collect_runs_destructors() {
+        struct DropFlag {
+            drops: std::rc::Rc<Cell<usize>>,
+            data: usize,
+        }
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.drops.set(self.drops.get() + 1);
+            }
+        }
+
+        impl AllocObject<TestTypeId> for DropFlag {
+            const TYPE_ID: TestTypeId = TestTypeId::Large;
+        }
+
+        let heap = ZapHeap::<TestHeader>::new();
+        let drops = std::rc::Rc::new(Cell::new(0));
+
+        heap.register_drop::<DropFlag>();
+
+        let live = heap.alloc(DropFlag { drops: drops.clone(), data: 1 }).unwrap();
+
+        heap.alloc(DropFlag { drops: drops.clone(), data: 2 }).unwrap();
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.collect();
+
+        assert!(drops.get() == 1);
+        assert!(unsafe { live.as_ref() }.data == 1);
+        assert!(heap.is_live(live.as_untyped()));
+
+        heap.collect();
+
+        assert!(drops.get() == 1);
+    }
+
+    #[test]
+    fn test_collect_resets_survivors() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let pinned = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        unsafe { heap.pin(pinned) };
+        unsafe { heap.mark_from_roots(&[live.as_untyped(), pinned.as_untyped()]) };
+        heap.collect();
+
+        let header = |ptr: RawPtr<SmallTestObj>| unsafe { ZapHeap::<TestHeader>::get_header(ptr.as_untyped()).as_ref() };
+
+        assert!(header(live).mark_state() == Mark::Allocated);
+        assert!(header(pinned).mark_state() == Mark::Allocated);
+        assert!(header(pinned).is_pinned());
+        assert!(heap.is_live(live.as_untyped()));
+        assert!(heap.live_set().len() == 2);
+
+        unsafe { heap.mark_from_roots(&[pinned.as_untyped()]) };
+        heap.collect();
+
+        assert!(!heap.is_live(live.as_untyped()));
+        assert!(heap.is_live(pinned.as_untyped()));
+    }
+
+    #[test]
+    fn test_sample_every_alloc() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.sample_allocs(1, 4);
+
+        for data in 0..3 {
+            heap.alloc(SmallTestObj { data }).unwrap();
+        }
+
+        heap.alloc_slice::<u8>(40, 0).unwrap();
+        heap.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        let samples = heap.samples();
+        let small = AllocSample {
+            size: size_of::<SmallTestObj>(),
+            type_id: TestTypeId::Small,
+        };
+        let expected = [
+            small,
+            AllocSample {
+                size: 40,
                 type_id: TestTypeId::Array,
-                mark,
-                size,
-                size_class
+            },
+            AllocSample {
+                size: size_of::<MediumTestObj>(),
+                type_id: TestTypeId::Medium,
+            },
+            small,
+        ];
+
+        assert!(samples == expected);
+
+        heap.sample_allocs(2, 4);
+
+        for data in 0..5 {
+            heap.alloc(SmallTestObj { data }).unwrap();
+        }
+
+        assert!(heap.samples() == [small, small]);
+    }
+
+    #[test]
+    fn test_alloc_after_prefault_free() {
+        let heap = ZapHeap::<TestHeader>::with_prealloc(2).unwrap();
+
+        heap.prefault_free();
+
+        let raw_ptr = heap.alloc(SmallTestObj { data: 9 }).unwrap();
+
+        assert!(unsafe { raw_ptr.as_ref() }.data == 9);
+        assert!(unsafe { &*heap.blocks.get() }.free.len() == 1);
+    }
+
+    #[test]
+    fn test_move_free_block_between_heaps() {
+        let donor = ZapHeap::<TestHeader>::with_prealloc(1).unwrap();
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.set_side_metadata(true);
+
+        let block = donor.take_free_block().unwrap();
+        let base = block.block.base();
+
+        assert!(donor.take_free_block().is_none());
+
+        heap.give_block(block);
+
+        let raw_ptr = heap.alloc(SmallTestObj { data: 7 }).unwrap();
+        let blocks = unsafe { &*heap.blocks.get() };
+        let head = blocks.head.as_ref().unwrap();
+
+        assert!(head.base() == base);
+        assert!(head.has_side_metadata());
+        assert!(blocks.free.is_empty());
+        assert!(unsafe { raw_ptr.as_ref() }.data == 7);
+        assert!(heap.object_base(raw_ptr.as_untyped()).is_some());
+    }
+
+    #[test]
+    fn test_dump_layout() {
+        let heap = ZapHeap::<TestHeader>::with_prealloc(2).unwrap();
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        for data in 0..20 {
+            heap.alloc(SmallTestObj { data }).unwrap();
+        }
+
+        let old = heap.alloc_old(SmallTestObj { data: 2 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped(), old.as_untyped()]) };
+
+        let layout = heap.dump_layout();
+        let line_of = |ptr: RawPtr<SmallTestObj>| (ptr.as_word() % constants::BLOCK_SIZE) / constants::LINE_SIZE;
+        let only_line = |line| (0..constants::LINE_COUNT).map(|line_num| line_num == line).collect::<Vec<_>>();
+
+        assert!(layout.len() == 3);
+        assert!(layout[0].space == AllocOp::Young && layout[0].role == BlockRole::Head);
+        assert!(layout[0].marked_lines == only_line(line_of(live)));
+        assert!(layout[1].space == AllocOp::Young && layout[1].role == BlockRole::Free);
+        assert!(layout[1].marked_lines.iter().all(|marked| !marked));
+        assert!(layout[2].space == AllocOp::Old && layout[2].role == BlockRole::Head);
+        assert!(layout[2].marked_lines == only_line(line_of(old)));
+        assert!(layout[0].base as usize == live.as_word() & !(constants::BLOCK_SIZE - 1));
+        assert!(heap.dump_layout() == layout);
+    }
+
+    #[test]
+    fn test_with_line_metadata() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let young = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let old = heap.alloc_old(SmallTestObj { data: 2 }).unwrap();
+        let outside = SmallTestObj { data: 3 };
+
+        let marked = unsafe {
+            heap.with_line_metadata(young.as_untyped(), |meta, mark_generation| {
+                meta[40] = mark_generation;
+                meta.len()
+            })
+        };
+
+        unsafe { heap.with_line_metadata(old.as_untyped(), |meta, mark_generation| meta[7] = mark_generation) };
+
+        let layout = heap.dump_layout();
+
+        assert!(marked == Some(constants::LINE_COUNT));
+        assert!(layout[0].marked_lines.iter().enumerate().all(|(line_num, marked)| *marked == (line_num == 40)));
+        assert!(layout[1].marked_lines.iter().enumerate().all(|(line_num, marked)| *marked == (line_num == 7)));
+        assert!(unsafe { heap.with_line_metadata(NonNull::from(&outside).cast(), |_, _| ()) }.is_none());
+    }
+
+    #[test]
+    fn test_atomic_marker_from_two_threads() {
+        let size = alloc_size::<SmallTestObj>();
+
+        for _ in 0..50 {
+            let heap = ZapHeap::<TestHeader>::new();
+            let objects: Vec<usize> = (0..2000).map(|data| heap.alloc(SmallTestObj { data }).unwrap().as_word()).collect();
+            let marker = unsafe { heap.atomic_marker() };
+
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    for object in objects.iter().step_by(2) {
+                        assert!(marker.mark_span(*object as *const u8, size));
+                    }
+                });
+
+                // the mutator keeps allocating, marking the rest as a barrier would
+                for object in objects.iter().skip(1).step_by(2) {
+                    heap.alloc(SmallTestObj { data: 0 }).unwrap();
+
+                    assert!(marker.mark_span(*object as *const u8, size));
+                }
+            });
+
+            assert!(objects.iter().all(|object| marker.is_marked(*object as *const u8)));
+
+            let layout = heap.dump_layout();
+            let marked = |object: &usize| {
+                let block = layout.iter().find(|block| block.base as usize == object & !(constants::BLOCK_SIZE - 1)).unwrap();
+
+                block.marked_lines[(object % constants::BLOCK_SIZE) / constants::LINE_SIZE]
+            };
+
+            assert!(objects.iter().all(marked));
+            assert!(!marker.mark_span(&size as *const usize as *const u8, size));
+        }
+    }
+
+    #[test]
+    fn test_iter_blocks_by_class() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+
+        for data in 0..(per_block * 2 + 1) {
+            heap.alloc(SmallTestObj { data: data as u32 }).unwrap();
+        }
+
+        for _ in 0..3 {
+            heap.alloc(MediumTestObj { data: [9; 256] }).unwrap();
+        }
+
+        heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
+
+        let small: Vec<_> = heap.iter_blocks(Some(SizeClass::Small)).collect();
+        let medium: Vec<_> = heap.iter_blocks(Some(SizeClass::Medium)).collect();
+
+        assert!(small.len() == 3);
+        assert!(medium.len() == 1);
+        assert!(medium[0].role == BlockRole::Overflow);
+        assert!(heap.iter_blocks(Some(SizeClass::Large)).count() == 0);
+        assert!(heap.iter_blocks(None).count() == 4);
+    }
+
+    #[test]
+    fn test_alloc_in_class_matches_alloc() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let classed = ZapHeap::<TestHeader>::new();
+        let offset = |ptr: NonNull<()>| ptr.as_ptr() as usize % constants::BLOCK_SIZE;
+
+        for data in 0..50 {
+            let small = heap.alloc(SmallTestObj { data }).unwrap();
+            let medium = heap.alloc(MediumTestObj { data: [1; 256] }).unwrap();
+            let small_classed = classed.alloc_in_class(SmallTestObj { data }, SizeClass::Small).unwrap();
+            let medium_classed = classed.alloc_in_class(MediumTestObj { data: [1; 256] }, SizeClass::Medium).unwrap();
+
+            assert!(offset(small.as_untyped()) == offset(small_classed.as_untyped()));
+            assert!(offset(medium.as_untyped()) == offset(medium_classed.as_untyped()));
+            assert!(unsafe { small_classed.as_ref() }.data == data);
+            assert!(unsafe { medium_classed.as_ref() }.data == [1; 256]);
+        }
+
+        let headers = |heap: &ZapHeap<TestHeader>| unsafe { &*heap.blocks.get() }.headers().count();
+
+        assert!(headers(&classed) == headers(&heap));
+        assert!(classed.verify() == Ok(()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_alloc_in_wrong_class_panics() {
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc_in_class(MediumTestObj { data: [1; 256] }, SizeClass::Small).unwrap();
+    }
+
+    #[test]
+    fn test_policy_collects_past_fragmentation() {
+        struct FragmentationPolicy {
+            limit: f64,
+        }
+
+        impl CollectionPolicy for FragmentationPolicy {
+            fn should_collect(&self, stats: &HeapStats) -> CollectDecision {
+                if stats.fragmentation > self.limit {
+                    CollectDecision::Collect
+                } else {
+                    CollectDecision::Skip
+                }
+            }
+        }
+
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut objects = Vec::new();
+        let mut remap = |_, _| ();
+
+        heap.set_policy(Box::new(FragmentationPolicy { limit: 0.5 }));
+
+        for _ in 0..constants::LINE_COUNT {
+            objects.push(heap.alloc_bytes(constants::LINE_SIZE - 16, 8, TestTypeId::Small).unwrap().as_untyped());
+        }
+
+        unsafe { heap.mark_from_roots(&objects) };
+
+        assert!(heap.stats().fragmentation <= 0.5);
+        assert!(heap.maybe_collect(&mut remap) == Ok(CollectDecision::Skip));
+        assert!(heap.stats().bytes_allocated > 0);
+
+        // every other line live still leaves the holes under the limit
+        unsafe { heap.mark_from_roots(&objects.iter().copied().step_by(2).collect::<Vec<_>>()) };
+
+        assert!(heap.stats().fragmentation <= 0.5);
+        assert!(heap.maybe_collect(&mut remap) == Ok(CollectDecision::Skip));
+
+        unsafe { heap.mark_from_roots(&objects[..10]) };
+
+        assert!(heap.stats().fragmentation > 0.5);
+        assert!(heap.maybe_collect(&mut remap) == Ok(CollectDecision::Collect));
+        assert!(heap.stats().bytes_allocated == 0);
+        assert!(heap.is_live(objects[0]));
+        assert!(!heap.is_live(objects[10]));
+    }
+
+    #[test]
+    fn test_alloc_reports_usable_size() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let (array, usable) = heap.alloc_array_usable(1).unwrap();
+
+        assert!(usable == constants::ALLOC_ALIGN);
+
+        unsafe { from_raw_parts_mut(array.as_ptr() as *mut u8, usable) }.fill(0xab);
+
+        let next = heap.alloc(SmallTestObj { data: 5 }).unwrap();
+        let (small, small_usable) = heap.alloc_usable(SmallTestObj { data: 6 }).unwrap();
+
+        assert!(unsafe { from_raw_parts_mut(array.as_ptr() as *mut u8, usable) }.iter().all(|byte| *byte == 0xab));
+        assert!(unsafe { next.as_ref() }.data == 5);
+        assert!(unsafe { small.as_ref() }.data == 6);
+        assert!(small_usable == add_alignment_padding(size_of::<SmallTestObj>()));
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_check_redzones_flags_overrun() {
+        let heap = ZapHeap::<TestHeader>::with_redzones();
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let array = heap.alloc_array(5).unwrap();
+        let last = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        assert!(heap.check_redzones() == Ok(()));
+        assert!(heap.alloc_array_usable(5).unwrap().1 == add_alignment_padding(5));
+
+        // still inside the array's padding, but past its recorded size
+        unsafe { *(array.as_ptr() as *mut u8).add(5) = 0 };
+
+        assert!(heap.check_redzones() == Err(ZapHeap::get_header(array.as_untyped())));
+
+        unsafe { heap.mark_from_roots(&[first.as_untyped(), array.as_untyped(), last.as_untyped()]) };
+        heap.sweep();
+
+        assert!(heap.check_redzones() == Err(ZapHeap::get_header(array.as_untyped())));
+        assert!(unsafe { first.as_ref() }.data == 1);
+        assert!(unsafe { last.as_ref() }.data == 3);
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_block_age_counts_survived_sweeps() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let root = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let old_block = root.as_word() & !(constants::BLOCK_SIZE - 1);
+
+        while blocks.used.is_empty() {
+            heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        }
+
+        let first_used = blocks.used[0].base();
+
+        for age in 1..=3 {
+            unsafe { heap.mark_from_roots(&[root.as_untyped()]) };
+            heap.sweep();
+
+            let block = blocks.block_for(old_block as *const u8).unwrap();
+
+            assert!(block.age() == age);
+            assert!(unsafe { root.as_ref() }.data == 1);
+        }
+
+        assert!(first_used as usize == old_block);
+        assert!(heap.dump_layout().iter().any(|layout| layout.age == 3));
+
+        blocks.reset();
+
+        assert!(blocks.free.iter().all(|block| block.age() == 0));
+    }
+
+    #[test]
+    fn test_alloc_array_of_aligned_slots() {
+        #[repr(align(32))]
+        #[derive(Copy, Clone)]
+        struct Aligned {
+            data: u64,
+        }
+
+        let heap = ZapHeap::<TestHeader>::new();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        let mut array = heap.alloc_array_of::<Aligned>(5).unwrap();
+        let header = unsafe { ZapHeap::<TestHeader>::get_header(array.as_untyped()).as_ref() };
+
+        assert!(array.as_word().is_multiple_of(32));
+
+        let slots = unsafe { array.as_mut_ref() };
+
+        assert!(slots.len() == 5);
+
+        for (index, slot) in slots.iter_mut().enumerate() {
+            slot.write(Aligned { data: index as u64 });
+        }
+
+        assert!(header.size() as usize == 5 * size_of::<Aligned>());
+        assert!(unsafe { slots[4].assume_init() }.data == 4);
+        assert!(heap.verify() == Ok(()));
+    }
+
+    #[test]
+    fn test_alloc_array_of_empty_and_overflow() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let empty = heap.alloc_array_of::<u64>(0).unwrap();
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(unsafe { empty.as_ref() }.is_empty());
+        assert!(unsafe { heap.alloc_array_of::<()>(1000).unwrap().as_ref() }.len() == 1000);
+        assert!(blocks.block_count() == 0);
+        assert!(heap.alloc_array_of::<u64>(usize::MAX / 4).err() == Some(AllocError::BadRequest));
+        assert!(heap.alloc_array_of::<u64>(ArraySize::MAX as usize / 8 + 1).err() == Some(AllocError::BadRequest));
+    }
+
+    #[test]
+    fn test_mark_follows_pointer_layout() {
+        #[repr(C)]
+        struct Pair {
+            left: *const (),
+            data: u64,
+            right: *const (),
+        }
+
+        impl AllocObject<TestTypeId> for Pair {
+            const TYPE_ID: TestTypeId = TestTypeId::Large;
+        }
+
+        impl PointerLayout for Pair {
+            const PTR_OFFSETS: &'static [usize] = &[core::mem::offset_of!(Pair, left), core::mem::offset_of!(Pair, right)];
+        }
+
+        let heap = ZapHeap::<TestHeader>::new();
+        let pair = |left: *const (), right: *const ()| heap.alloc(Pair { left, data: 0, right }).unwrap().as_untyped();
+
+        heap.register_layout::<Pair>();
+
+        let leaf = heap.alloc(SmallTestObj { data: 1 }).unwrap().as_untyped();
+        let unreached = heap.alloc(SmallTestObj { data: 2 }).unwrap().as_untyped();
+        let left = pair(leaf.as_ptr(), std::ptr::null());
+        let right = heap.alloc(SmallTestObj { data: 3 }).unwrap().as_untyped();
+        let root = pair(left.as_ptr(), right.as_ptr());
+
+        unsafe { heap.mark_from_roots(&[root]) };
+
+        assert!(is_marked(root));
+        assert!(is_marked(left));
+        assert!(is_marked(right));
+        assert!(is_marked(leaf));
+        assert!(!is_marked(unreached));
+    }
+
+    #[test]
+    fn test_sweep_frees_empty_overflow_block() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let mut live = None;
+
+        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size::<MediumTestObj>()) {
+            live = Some(heap.alloc(MediumTestObj { data: [3; 256] }).unwrap());
+        }
+
+        let live = live.unwrap();
+        let overflow = blocks.overflow.as_ref().unwrap().base();
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.sweep();
+
+        assert!(blocks.overflow.as_ref().unwrap().base() == overflow);
+        assert!(unsafe { live.as_ref() }.data == [3; 256]);
+
+        unsafe { heap.mark_from_roots(&[]) };
+        heap.sweep();
+
+        assert!(blocks.overflow.is_none());
+        assert!(blocks.free.len() == 1);
+        assert!(blocks.free[0].base() == overflow);
+        assert!(blocks.free[0].current_hole_size() == constants::BLOCK_CAPACITY);
+    }
+
+    #[test]
+    fn test_alloc_headerless() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let before = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let bytes = heap.alloc_headerless(100, 64).unwrap();
+        let large = heap.alloc_headerless(constants::BLOCK_SIZE * 2, 4096).unwrap();
+        let after = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        assert!(bytes.as_word().is_multiple_of(64));
+        assert!(large.as_word().is_multiple_of(4096));
+
+        unsafe {
+            from_raw_parts_mut(bytes.as_ptr() as *mut u8, 100).fill(0xff);
+            from_raw_parts_mut(large.as_ptr() as *mut u8, constants::BLOCK_SIZE * 2).fill(0xff);
+        }
+
+        let blocks = unsafe { &*heap.blocks.get() };
+
+        assert!(unsafe { before.as_ref() }.data == 1);
+        assert!(unsafe { after.as_ref() }.data == 2);
+        assert!(blocks.headers().count() == 2);
+        assert!(heap.object_base(bytes.as_untyped()).is_none());
+        assert!(heap.verify() == Ok(()));
+
+        unsafe { heap.mark_from_roots(&[before.as_untyped(), after.as_untyped()]) };
+        heap.sweep();
+
+        assert!(blocks.moved_large.is_empty());
+        assert!(heap.verify() == Ok(()));
+        assert!(heap.alloc_headerless(8, 3).err() == Some(AllocError::BadRequest));
+    }
+
+    #[test]
+    fn test_heap_survives_moves() {
+        let heap = ZapHeap::<TestHeader>::with_redzones();
+        let mut live = vec![heap.alloc(SmallTestObj { data: 0 }).unwrap()];
+
+        heap.set_side_metadata(true);
+        live.push(heap.alloc(SmallTestObj { data: 1 }).unwrap());
+
+        let boxed = Box::new(heap);
+
+        live.push(boxed.alloc(SmallTestObj { data: 2 }).unwrap());
+        boxed.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+
+        let mut heaps = vec![*boxed];
+
+        heaps.push(ZapHeap::new());
+        heaps.reserve(100);
+
+        let heap = &heaps[0];
+
+        live.push(heap.alloc(SmallTestObj { data: 3 }).unwrap());
+        unsafe { heap.mark_from_roots(&live.iter().map(|ptr| ptr.as_untyped()).collect::<Vec<_>>()) };
+        heap.sweep();
+        live.push(heap.alloc(SmallTestObj { data: 4 }).unwrap());
+
+        for (data, ptr) in live.iter().enumerate() {
+            assert!(unsafe { ptr.as_ref() }.data == data as u32);
+            assert!(heap.is_live(ptr.as_untyped()));
+        }
+
+        assert!(heap.verify() == Ok(()));
+        assert!(heap.check_redzones() == Ok(()));
+    }
+
+    #[test]
+    fn test_realloc_array_grows_into_large_space() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let array = heap.alloc_array(4096).unwrap();
+
+        unsafe {
+            let bytes = from_raw_parts_mut(array.as_ptr() as *mut u8, 4096);
+
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+        }
+
+        let old_header: NonNull<TestHeader> = ZapHeap::get_header(array.as_untyped());
+
+        assert!(unsafe { old_header.as_ref() }.size_class == SizeClass::Medium);
+
+        let grown = unsafe { heap.realloc_array(array, 32 * 1024) }.unwrap();
+        let header: &TestHeader = unsafe { ZapHeap::get_header(grown.as_untyped()).as_ref() };
+
+        assert!(header.size_class == SizeClass::Large);
+        assert!(header.size == 32 * 1024);
+        assert!(unsafe { &*heap.blocks.get() }.large.len() == 1);
+
+        let bytes = unsafe { from_raw_parts(grown.as_ptr(), 32 * 1024) };
+
+        assert!(bytes[..4096].iter().enumerate().all(|(i, byte)| *byte == i as u8));
+        assert!(bytes[4096..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_defrag_block_keeps_alignment() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let aligned = heap.alloc(AlignedTestObj { data: 1 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        let small = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[aligned.as_untyped(), small.as_untyped()]) };
+
+        let index = heap.block_index_of(aligned.as_untyped()).unwrap();
+        let mut moves = Vec::new();
+
+        heap.defrag_block(index, &mut |old, new| moves.push((old, new)));
+
+        let aligned = moves.iter().find(|(old, _)| *old == aligned.as_untyped()).map_or(aligned.as_untyped(), |(_, new)| *new);
+        let (_, small) = moves.iter().find(|(old, _)| *old == small.as_untyped()).unwrap();
+
+        assert!((aligned.as_ptr() as usize).is_multiple_of(64));
+        assert!(unsafe { &*(aligned.as_ptr() as *const AlignedTestObj) }.data == 1);
+        assert!(unsafe { &*(small.as_ptr() as *const SmallTestObj) }.data == 3);
+    }
+
+    #[test]
+    fn test_defrag_block_leaves_one_hole() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut live = Vec::new();
+
+        // every other object dies, leaving holes between the survivors
+        for data in 0..16 {
+            let ptr = heap.alloc(MediumTestObj { data: [data; 256] }).unwrap();
+
+            if data % 2 == 0 {
+                live.push(ptr);
             }
         }
-        fn mark(&mut self) {
-            self.mark = Mark::Marked;
-        }
 
-        fn is_marked(&self) -> bool {
-            self.mark == Mark::Marked
-        }
+        unsafe { heap.mark_from_roots(&live.iter().map(|ptr| ptr.as_untyped()).collect::<Vec<_>>()) };
 
-        fn type_id(&self) -> Self::TypeId {
-            self.type_id
+        let index = heap.block_index_of(live[0].as_untyped()).unwrap();
+        let mut moves = Vec::new();
+        let consolidated = heap.defrag_block(index, &mut |old, new| moves.push((old, new)));
+
+        assert!(moves.len() == 7);
+        assert!(consolidated == 7 * heap.alloc_size_of(size_of::<MediumTestObj>()));
+
+        for ptr in live.iter_mut() {
+            if let Some((_, new)) = moves.iter().find(|(old, _)| *old == ptr.as_untyped()) {
+                *ptr = RawPtr::new(new.as_ptr() as *const MediumTestObj);
+            }
         }
 
-        fn size(&self) -> u32 {
-            self.size }
+        let blocks = unsafe { &*heap.blocks.get() };
+        let block = blocks.overflow.as_ref().unwrap();
+        let live_lines = (8 * heap.alloc_size_of(size_of::<MediumTestObj>())).div_ceil(constants::LINE_SIZE);
 
-        fn size_class(&self) -> SizeClass {
-            self.size_class
+        assert!(block.objects().count() == 8);
+        assert!(block.occupied_lines() == live_lines);
+        assert!(block.holes() == vec![(0, (constants::LINE_COUNT - live_lines) * constants::LINE_SIZE)]);
+
+        for (data, ptr) in live.iter().enumerate() {
+            assert!(unsafe { ptr.as_ref() }.data == [2 * data as u8; 256]);
         }
+
+        heap.sweep();
+
+        assert!(heap.verify() == Ok(()));
     }
 
+    #[cfg(feature = "testing")]
     #[test]
-    fn test_alloc_small_obj() {
+    fn test_fail_after_fails_third_block() {
         let heap = ZapHeap::<TestHeader>::new();
-        let small_obj = SmallTestObj { data: 333};
-        let ptr = heap.alloc(small_obj);
-        let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
-        let blocks = unsafe { &mut *heap.blocks.get() };
-        let alloc_size = alloc_size::<SmallTestObj>();
 
-        assert!(small_obj_copy.data == 333);
-        assert!(blocks.block_count() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.head.as_ref().unwrap().current_hole_size() == constants::BLOCK_CAPACITY - alloc_size);
+        heap.set_fail_after(2);
+
+        let error = loop {
+            match heap.alloc(SmallTestObj { data: 0 }) {
+                Ok(_) => assert!(heap.block_count() <= 2),
+                Err(error) => break error,
+            }
+        };
+
+        assert!(error == AllocError::OOM);
+        assert!(heap.block_count() == 2);
+        assert!(heap.alloc(SmallTestObj { data: 1 }).is_ok());
+        assert!(heap.block_count() == 3);
     }
 
     #[test]
-    fn test_alloc_many_small_obj() {
+    fn test_live_bytes_exact() {
         let heap = ZapHeap::<TestHeader>::new();
-        let blocks = unsafe { &mut *heap.blocks.get() };
-        let alloc_size = alloc_size::<SmallTestObj>();
+        let header_alloc_size = add_alignment_padding(size_of::<TestHeader>());
+        let small = header_alloc_size + add_alignment_padding(size_of::<SmallTestObj>());
+        let medium = header_alloc_size + 256;
+        let array = header_alloc_size + add_alignment_padding(100);
+        let root = heap.alloc(SmallTestObj { data: 0 }).unwrap();
 
-        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
-            let small_obj = SmallTestObj { data: 333};
-            let ptr = heap.alloc(small_obj);
-            let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
-            assert!(small_obj_copy.data == 333);
-        }
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        heap.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+        heap.alloc_old(SmallTestObj { data: 2 }).unwrap();
+        heap.alloc_array(100).unwrap();
+        heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
 
-        assert!(blocks.block_count() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.used.len() == 0);
-        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+        let large = header_alloc_size + add_alignment_padding(constants::LARGE_OBJECT_MIN);
 
-        let small_obj = SmallTestObj { data: 333};
-        let ptr = heap.alloc(small_obj);
-        let small_obj_copy = unsafe { &*(ptr.unwrap().as_ptr()) };
+        assert!(heap.live_bytes_exact() == 3 * small + medium + array + large);
 
-        assert!(small_obj_copy.data == 333);
-        assert!(blocks.block_count() == 2);
-        assert!(blocks.used.len() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+        unsafe { heap.mark_from_roots(&[root.as_untyped()]) };
+
+        assert!(heap.live_bytes_exact() == small);
+
+        heap.sweep();
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        assert!(heap.live_bytes_exact() == 2 * small);
     }
 
     #[test]
-    fn test_small_obj_header() {
+    fn test_alloc_at_reuses_dead_slot() {
         let heap = ZapHeap::<TestHeader>::new();
-        let small_obj = SmallTestObj { data: 333};
-        let raw_ptr = heap.alloc(small_obj).unwrap();
-        let header_ptr = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let header: &TestHeader = unsafe { &*header_ptr.as_ptr() };
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let last = heap.alloc(SmallTestObj { data: 3 }).unwrap();
 
-        assert!(header.type_id == TestTypeId::Small);
-        assert!(header.size == size_of::<SmallTestObj>() as u32);
-        assert!(header.size_class == SizeClass::Small);
-        assert!(header.mark == Mark::Allocated);
+        assert!(unsafe { heap.alloc_at(SmallTestObj { data: 4 }, dead.as_untyped()) } == Err(AllocError::BadRequest));
+
+        unsafe { heap.mark_from_roots(&[first.as_untyped(), last.as_untyped()]) };
+        heap.sweep();
+
+        assert!(unsafe { heap.alloc_at(SmallTestObj { data: 4 }, first.as_untyped()) } == Err(AllocError::BadRequest));
+        assert!(unsafe { heap.alloc_at(MediumTestObj { data: [0; 256] }, dead.as_untyped()) } == Err(AllocError::BadRequest));
+
+        let reused = unsafe { heap.alloc_at(SmallTestObj { data: 4 }, dead.as_untyped()) }.unwrap();
+
+        assert!(reused == dead);
+        assert!(unsafe { reused.as_ref() }.data == 4);
+        assert!(heap.is_live(reused.as_untyped()));
+        assert!(unsafe { heap.alloc_at(SmallTestObj { data: 5 }, dead.as_untyped()) } == Err(AllocError::BadRequest));
+        assert!(heap.verify() == Ok(()));
     }
 
     #[test]
-    fn test_get_object() {
+    fn test_alloc_at_reuses_freed_slot() {
         let heap = ZapHeap::<TestHeader>::new();
-        let small_obj = SmallTestObj { data: 333};
-        let raw_ptr = heap.alloc(small_obj).unwrap();
-        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let obj_ptr = ZapHeap::get_object(header_ptr);
-        let obj = unsafe { &*(obj_ptr.as_ptr() as *const SmallTestObj) };
+        let freed = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let last = heap.alloc(SmallTestObj { data: 2 }).unwrap();
 
-        assert!(obj.data == 333);
+        unsafe { heap.dealloc(freed).unwrap() };
+
+        assert!(unsafe { heap.alloc_at(MediumTestObj { data: [0; 256] }, freed.as_untyped()) } == Err(AllocError::BadRequest));
+
+        let reused = unsafe { heap.alloc_at(SmallTestObj { data: 3 }, freed.as_untyped()) }.unwrap();
+
+        assert!(reused == freed);
+        assert!(unsafe { reused.as_ref() }.data == 3);
+        assert!(heap.is_live(reused.as_untyped()));
+        assert!(unsafe { heap.alloc_at(SmallTestObj { data: 4 }, freed.as_untyped()) } == Err(AllocError::BadRequest));
+
+        // the last bumped object goes back to the cursor instead
+        unsafe { heap.dealloc(last).unwrap() };
+
+        assert!(unsafe { heap.alloc_at(SmallTestObj { data: 5 }, last.as_untyped()) } == Err(AllocError::BadRequest));
+        assert!(heap.verify() == Ok(()));
     }
 
     #[test]
-    fn test_alloc_medium_object() {
+    fn test_heap_with_other_line_sizes() {
+        assert!(ZapHeap::<TestHeader>::with_line_size(96).is_err());
+        assert!(ZapHeap::<TestHeader>::with_line_size(constants::BLOCK_SIZE).is_err());
+
+        for line in [64, 256] {
+            let heap = ZapHeap::<TestHeader>::with_line_size(line).unwrap();
+            let mut live = Vec::new();
+
+            for data in 0..2000 {
+                let ptr = heap.alloc(SmallTestObj { data }).unwrap();
+
+                if data % 50 == 0 {
+                    live.push(ptr);
+                }
+            }
+
+            unsafe { heap.mark_from_roots(&live.iter().map(|ptr| ptr.as_untyped()).collect::<Vec<_>>()) };
+
+            assert!(heap.verify() == Ok(()));
+
+            heap.sweep();
+
+            let line_count = constants::BLOCK_CAPACITY.div_ceil(line);
+
+            assert!(heap.line_size() == line);
+            assert!(heap.dump_layout().iter().all(|layout| layout.marked_lines.len() == line_count));
+            assert!(heap.holes().all(|(_, offset, size)| offset % line == 0 && offset + size <= constants::BLOCK_CAPACITY));
+
+            for data in 0..2000 {
+                heap.alloc(SmallTestObj { data }).unwrap();
+            }
+
+            for (index, ptr) in live.iter().enumerate() {
+                assert!(unsafe { ptr.as_ref() }.data == 50 * index as u32);
+            }
+
+            let aligned = heap.alloc_line_aligned(SmallTestObj { data: 0 }).unwrap();
+
+            let header: NonNull<TestHeader> = ZapHeap::get_header(aligned.as_untyped());
+
+            assert!((header.as_ptr() as usize).is_multiple_of(line));
+            assert!(heap.verify() == Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_iter_entries() {
         let heap = ZapHeap::<TestHeader>::new();
-        let small_obj = MediumTestObj { data: [9; 256] };
-        let raw_ptr = heap.alloc(small_obj).unwrap();
-        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let obj_ptr = ZapHeap::get_object(header_ptr);
-        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+        let small = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let array = heap.alloc_array(100).unwrap();
+        let old = heap.alloc_old(SmallTestObj { data: 2 }).unwrap();
+        let large = heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
+        let entries: Vec<_> = heap.iter_entries().collect();
 
-        assert!(obj.data == [9; 256]);
+        assert!(entries.len() == 4);
+
+        for (header, object, type_id, size) in entries.iter() {
+            let expected: &TestHeader = unsafe { header.as_ref() };
+
+            assert!(*object == ZapHeap::get_object(*header));
+            assert!(*type_id == expected.type_id);
+            assert!(*size == expected.size);
+        }
+
+        let find = |ptr: NonNull<()>| entries.iter().find(|entry| entry.1 == ptr).map(|entry| (entry.2, entry.3));
+
+        assert!(find(small.as_untyped()) == Some((TestTypeId::Small, size_of::<SmallTestObj>() as u32)));
+        assert!(find(array.as_untyped()) == Some((TestTypeId::Array, 100)));
+        assert!(find(old.as_untyped()) == Some((TestTypeId::Small, size_of::<SmallTestObj>() as u32)));
+        assert!(find(large.as_untyped()) == Some((TestTypeId::Array, constants::LARGE_OBJECT_MIN as u32)));
     }
 
     #[test]
-    fn test_over_flow_alloc() {
+    fn test_medium_alloc_uses_lower_head_hole() {
         let heap = ZapHeap::<TestHeader>::new();
         let blocks = unsafe { &mut *heap.blocks.get() };
-        let alloc_size = alloc_size::<MediumTestObj>();
+        let line = constants::LINE_SIZE;
 
-        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
-            let medium_obj = MediumTestObj { data: [9; 256] };
-            let raw_ptr = heap.alloc(medium_obj).unwrap();
-            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-            let obj_ptr = ZapHeap::get_object(header_ptr);
-            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+        heap.alloc(SmallTestObj { data: 0 }).unwrap();
 
-            assert!(obj.data == [9; 256]);
+        // the head bumps lines 101 and up, with lines 0 to 99 free below
+        let head = blocks.head.as_mut().unwrap();
+        let (cursor, limit) = (head.cursor(), unsafe { head.base().add(101 * line) });
+
+        head.mark_line(100);
+        head.rewind_to(cursor, limit);
+
+        let medium = heap.alloc(MediumTestObj { data: [7; 256] }).unwrap();
+        let head = blocks.head.as_ref().unwrap();
+
+        assert!(blocks.overflow.is_none());
+        assert!(head.contains(medium.as_ptr() as *const u8));
+        assert!(head.offset_of(medium.as_ptr() as *const u8) < 100 * line);
+        assert!(head.cursor() == cursor);
+
+        let small = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        assert!(small.as_word() < cursor as usize && small.as_word() > limit as usize);
+
+        for data in 0..1000 {
+            heap.alloc(SmallTestObj { data }).unwrap();
         }
 
-        assert!(blocks.block_count() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.recycle.len() == 0);
-        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+        assert!(unsafe { medium.as_ref() }.data == [7; 256]);
+        assert!(heap.verify() == Ok(()));
+    }
 
-        let medium_obj = MediumTestObj { data: [9; 256] };
-        let raw_ptr = heap.alloc(medium_obj).unwrap();
-        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let obj_ptr = ZapHeap::get_object(header_ptr);
-        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+    #[test]
+    fn test_reset_to_refuses_lower_hole_allocs() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let blocks = unsafe { &mut *heap.blocks.get() };
+        let line = constants::LINE_SIZE;
+        let kept = heap.alloc(SmallTestObj { data: 0 }).unwrap();
 
-        assert!(obj.data == [9; 256]);
-        assert!(blocks.block_count() == 2);
-        assert!(blocks.overflow.is_some());
-        assert!(blocks.head.is_some());
-        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+        let head = blocks.head.as_mut().unwrap();
+        let (cursor, limit) = (head.cursor(), unsafe { head.base().add(101 * line) });
+
+        head.mark_line(100);
+        head.rewind_to(cursor, limit);
+
+        let checkpoint = heap.checkpoint();
+        let medium = heap.alloc(MediumTestObj { data: [7; 256] }).unwrap();
+        let headers = blocks.headers().count();
+
+        assert!(heap.reset_to(checkpoint) == Err(AllocError::BadRequest));
+        assert!(blocks.headers().count() == headers);
+        assert!(unsafe { medium.as_ref() }.data == [7; 256]);
+        assert!(unsafe { kept.as_ref() }.data == 0);
+
+        let checkpoint = heap.checkpoint();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        assert!(heap.reset_to(checkpoint) == Ok(()));
+        assert!(blocks.headers().count() == headers);
     }
 
     #[test]
-    fn test_use_recycling() {
+    fn test_reset_to_after_medium_search() {
         let heap = ZapHeap::<TestHeader>::new();
         let blocks = unsafe { &mut *heap.blocks.get() };
-        let alloc_size = alloc_size::<MediumTestObj>();
+        let line = constants::LINE_SIZE;
 
-        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
-            let medium_obj = MediumTestObj { data: [9; 256] };
-            let raw_ptr = heap.alloc(medium_obj).unwrap();
-            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-            let obj_ptr = ZapHeap::get_object(header_ptr);
-            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+        heap.alloc(SmallTestObj { data: 0 }).unwrap();
 
-            assert!(obj.data == [9; 256]);
+        // every line below the hole is taken, so the medium search finds nothing
+        let head = blocks.head.as_mut().unwrap();
+        let (cursor, limit) = (head.cursor(), unsafe { head.base().add(101 * line) });
+
+        for line_num in 0..=100 {
+            head.mark_line(line_num);
         }
 
-        assert!(blocks.block_count() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.recycle.len() == 0);
-        assert!(blocks.head.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+        head.rewind_to(cursor, limit);
 
-        for _ in 0..(constants::BLOCK_CAPACITY / alloc_size) {
-            let medium_obj = MediumTestObj { data: [9; 256] };
-            let raw_ptr = heap.alloc(medium_obj).unwrap();
-            let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-            let obj_ptr = ZapHeap::get_object(header_ptr);
-            let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+        let headers = blocks.headers().count();
+        let checkpoint = heap.checkpoint();
 
-            assert!(obj.data == [9; 256]);
+        heap.alloc(MediumTestObj { data: [7; 256] }).unwrap();
+
+        assert!(heap.stats().total_hole_scans > 0);
+        assert!(heap.reset_to(checkpoint) == Ok(()));
+        assert!(blocks.headers().count() == headers);
+        assert!(blocks.overflow.is_none());
+    }
+
+    #[test]
+    fn test_alloc_large_aligned() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let size = 32 * 1024;
+
+        assert!(heap.alloc_large_aligned(1024, 4096).err() == Some(AllocError::BadRequest));
+        assert!(heap.alloc_large_aligned(size, 3000).err() == Some(AllocError::BadRequest));
+
+        for align in [constants::ALLOC_ALIGN, 4096, 64 * 1024] {
+            let buffer = heap.alloc_large_aligned(size, align).unwrap();
+            let header: &TestHeader = unsafe { ZapHeap::get_header(buffer.as_untyped()).as_ref() };
+            let bytes = unsafe { from_raw_parts(buffer.as_ptr(), size as usize) };
+
+            assert!(buffer.as_word().is_multiple_of(align));
+            assert!(header.size_class == SizeClass::Large);
+            assert!(header.size == size);
+            assert!(bytes.iter().all(|byte| *byte == 0));
         }
 
-        assert!(blocks.block_count() == 2);
-        assert!(blocks.overflow.is_some());
-        assert!(blocks.head.is_some());
-        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY % alloc_size));
+        assert!(heap.verify() == Ok(()));
+    }
 
-        let medium_obj = MediumTestObj { data: [9; 256] };
-        let raw_ptr = heap.alloc(medium_obj).unwrap();
-        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let obj_ptr = ZapHeap::get_object(header_ptr);
-        let obj = unsafe { &*(obj_ptr.as_ptr() as *const MediumTestObj) };
+    #[test]
+    fn test_live_set_diff() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let kept = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let freed = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let before = heap.live_set();
 
-        assert!(obj.data == [9; 256]);
-        assert!(blocks.block_count() == 3);
-        assert!(blocks.overflow.is_some());
-        assert!(blocks.recycle.len() == 1);
-        assert!(blocks.head.is_some());
-        assert!(blocks.overflow.as_ref().unwrap().current_hole_size() == (constants::BLOCK_CAPACITY - alloc_size));
+        assert!(before.len() == 2);
+
+        let added = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        let large = heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
+
+        unsafe { heap.mark_from_roots(&[kept.as_untyped(), added.as_untyped(), large.as_untyped()]) };
+        heap.sweep();
+
+        let after = heap.live_set();
+        let mut expected_added = vec![added.as_word(), large.as_word()];
+
+        expected_added.sort();
+
+        assert!(ZapHeap::<TestHeader>::diff(&before, &after) == (expected_added, vec![freed.as_word()]));
+        assert!(ZapHeap::<TestHeader>::diff(&after, &after) == (Vec::new(), Vec::new()));
     }
 
     #[test]
-    fn test_array_alloc() {
+    fn test_dealloc_twice_is_detected() {
         let heap = ZapHeap::<TestHeader>::new();
-        let blocks = unsafe { &mut *heap.blocks.get() };
-        let alloc_size = size_of::<MediumTestObj>() as u32;
-        let raw_ptr = heap.alloc_array(alloc_size as u32).unwrap();
-        let header_ptr: NonNull<TestHeader> = ZapHeap::get_header(raw_ptr.as_untyped()); 
-        let header = unsafe { &*header_ptr.as_ptr() };
+        let first = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let last = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let large = heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
 
-        assert!(header.type_id == TestTypeId::Array);
-        assert!(header.size_class == SizeClass::Medium);
-        assert!(header.mark == Mark::Allocated);
-        assert!(header.size == alloc_size);
+        unsafe {
+            assert!(heap.dealloc(first) == Ok(()));
+            assert!(heap.dealloc(first) == Err(AllocError::DoubleFree));
+            assert!(heap.dealloc(last) == Ok(()));
+            assert!(heap.dealloc(last) == Err(AllocError::DoubleFree));
+            assert!(heap.dealloc(large) == Ok(()));
+            assert!(heap.dealloc(large) == Err(AllocError::DoubleFree));
+        }
+
+        assert!(!heap.is_live(first.as_untyped()));
+        assert!(heap.live_bytes_exact() == 0);
+        assert!(unsafe { &*heap.blocks.get() }.bytes_allocated == 0);
+
+        heap.sweep();
+
+        assert!(unsafe { &*heap.blocks.get() }.moved_large.is_empty());
+    }
+
+    #[test]
+    fn test_dealloc_after_sweep() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let survivor = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let large = heap.alloc_array(constants::LARGE_OBJECT_MIN as ArraySize).unwrap();
+
+        unsafe { heap.mark_from_roots(&[survivor.as_untyped(), large.as_untyped()]) };
+        heap.sweep();
+
+        let fresh = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+
+        unsafe {
+            assert!(heap.dealloc(survivor) == Ok(()));
+            assert!(heap.dealloc(large) == Ok(()));
+        }
+
+        assert!(unsafe { &*heap.blocks.get() }.bytes_allocated <= alloc_size::<SmallTestObj>());
+        assert!(heap.is_live(fresh.as_untyped()));
+        assert!(!heap.is_live(survivor.as_untyped()));
+    }
+
+    #[test]
+    fn test_stats_count_refills_and_hole_scans() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let mut live = Vec::new();
+
+        for data in 0..(3 * per_block) {
+            let ptr = heap.alloc(SmallTestObj { data: data as u32 }).unwrap();
+
+            if data % 40 == 0 {
+                live.push(ptr.as_untyped());
+            }
+        }
+
+        heap.alloc(MediumTestObj { data: [0; 256] }).unwrap();
+
+        // fresh blocks are one hole, so filling them never searches
+        assert!(heap.stats().total_refills == 3);
+        assert!(heap.stats().total_hole_scans == 0);
+
+        unsafe { heap.mark_from_roots(&live) };
+        heap.sweep();
+
+        for data in 0..per_block {
+            heap.alloc(SmallTestObj { data: data as u32 }).unwrap();
+        }
+
+        let stats = heap.stats();
+
+        assert!(stats.total_refills > 3);
+        // the survivors split each recycled block into holes to search
+        assert!(stats.total_hole_scans > 0);
     }
 }
@@ -0,0 +1,89 @@
+use core::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use crate::allocator::AllocHeader;
+use crate::heap::{BlockPool, ZapHeap};
+
+// each thread allocates from its own heap without locking; the pool is only
+// touched when a heap runs out of blocks or sweeps some free
+pub struct ThreadLocalHeapPool<H: AllocHeader> {
+    blocks: BlockPool,
+    _header_type: PhantomData<fn() -> H>,
+}
+
+impl<H: AllocHeader> ThreadLocalHeapPool<H> {
+    pub fn new() -> ThreadLocalHeapPool<H> {
+        ThreadLocalHeapPool {
+            blocks: Arc::new(Mutex::new(Vec::new())),
+            _header_type: PhantomData,
+        }
+    }
+
+    pub fn heap(&self) -> ZapHeap<H> {
+        ZapHeap::with_pool(self.blocks.clone())
+    }
+
+    pub fn pooled_block_count(&self) -> usize {
+        self.blocks.lock().unwrap().len()
+    }
+}
+
+impl<H: AllocHeader> Clone for ThreadLocalHeapPool<H> {
+    fn clone(&self) -> ThreadLocalHeapPool<H> {
+        ThreadLocalHeapPool {
+            blocks: self.blocks.clone(),
+            _header_type: PhantomData,
+        }
+    }
+}
+
+impl<H: AllocHeader> Default for ThreadLocalHeapPool<H> {
+    fn default() -> ThreadLocalHeapPool<H> {
+        ThreadLocalHeapPool::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::AllocRaw;
+    use crate::constants;
+    use crate::heap::tests::{alloc_size, SmallTestObj, TestHeader};
+    use std::thread;
+
+    #[test]
+    fn test_freed_blocks_migrate_to_pool() {
+        let pool = ThreadLocalHeapPool::<TestHeader>::new();
+        let per_block = constants::BLOCK_CAPACITY / alloc_size::<SmallTestObj>();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+
+                thread::spawn(move || {
+                    let heap = pool.heap();
+
+                    for i in 0..(3 * per_block) {
+                        heap.alloc(SmallTestObj { data: i as u32 }).unwrap();
+                    }
+
+                    unsafe { heap.mark_from_roots(&[]) };
+                    heap.sweep();
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let pooled = pool.pooled_block_count();
+
+        assert!(pooled >= 2);
+
+        let heap = pool.heap();
+
+        heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        assert!(pool.pooled_block_count() == pooled - 1);
+    }
+}
@@ -1,18 +1,54 @@
+// tests link std either way, so `cargo check-no-std` is what checks the
+// library really builds without it
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
+
 mod block;
 mod constants;
 mod bump_block;
+mod dyn_registry;
+mod handle;
 mod heap;
+#[cfg(feature = "std")]
+mod heap_pool;
+mod policy;
+mod segregated_heap;
 mod raw_ptr;
+#[cfg(not(feature = "std"))]
+mod type_map;
+mod weak_ref;
+mod zap_box;
 mod allocator;
 
+pub use crate::bump_block::HoleStrategy;
+
 pub use crate::block::{
-    BlockError
+    BlockError, BlockSource
 };
 
 pub use crate::allocator::{
-    AllocError, AllocHeader, AllocObject, AllocRaw, AllocTypeId, ArraySize, Mark, SizeClass,
+    AllocError, AllocHeader, AllocObject, AllocRaw, AllocTypeId, ArraySize, Mark, PointerLayout, SizeClass, Trace,
 };
 
-pub use crate::heap::ZapHeap;
+pub use crate::dyn_registry::DynRegistry;
+
+pub use crate::handle::Handle;
+
+pub use crate::heap::{AllocEvent, AllocOp, AllocSample, AtomicMarker, BlockLayout, BlockRole, BlockTarget, Checkpoint, FreeBlock, HeapError, ZapHeap};
+
+#[cfg(feature = "profiling")]
+pub use crate::heap::LatencyStats;
+
+pub use crate::policy::{CollectDecision, CollectionPolicy, HeapStats};
+
+#[cfg(feature = "std")]
+pub use crate::heap_pool::ThreadLocalHeapPool;
+
+pub use crate::segregated_heap::SizeSegregatedHeap;
+
+pub use crate::raw_ptr::{ArrayPtr, NullPtrError, RawPtr};
+
+pub use crate::weak_ref::WeakRef;
 
-pub use crate::raw_ptr::RawPtr;
+pub use crate::zap_box::ZapBox;
@@ -0,0 +1,23 @@
+// a snapshot of both spaces, taken by ZapHeap::stats
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapStats {
+    pub bytes_allocated: usize,
+    pub blocks: usize,
+    pub free_blocks: usize,
+    pub large_objects: usize,
+    pub fragmentation: f64,
+    pub total_refills: usize,
+    pub total_hole_scans: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CollectDecision {
+    Skip,
+    Collect,
+    Evacuate,
+}
+
+// decides, from the marks of the last mark phase, what maybe_collect runs
+pub trait CollectionPolicy {
+    fn should_collect(&self, stats: &HeapStats) -> CollectDecision;
+}
@@ -1,47 +1,256 @@
-use std::ptr::NonNull;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
 
-pub struct RawPtr<T: Sized> {
+use crate::allocator::{add_alignment_padding, AllocHeader};
+
+pub struct RawPtr<T: ?Sized> {
     ptr: NonNull<T>,
 }
 
-impl<T: Sized> RawPtr<T> {
+impl<T: ?Sized> RawPtr<T> {
     pub fn new(ptr: *const T) -> RawPtr<T> {
         RawPtr {
             ptr: unsafe { NonNull::new_unchecked(ptr as *mut T) },
         }
     }
 
+    pub fn from_nonnull(ptr: NonNull<T>) -> RawPtr<T> {
+        RawPtr { ptr }
+    }
+
+    pub fn as_nonnull(&self) -> NonNull<T> {
+        self.ptr
+    }
+
     pub fn as_ptr(self) -> *const T {
         self.ptr.as_ptr()
     }
 
     pub fn as_word(self) -> usize {
-        self.ptr.as_ptr() as usize
+        self.ptr.as_ptr() as *const u8 as usize
     }
 
     pub fn as_untyped(self) -> NonNull<()> {
         self.ptr.cast()
     }
 
+    /// # Safety
+    ///
+    /// The pointer must point to a live, initialized `T`.
     pub unsafe fn as_ref(&self) -> &T {
         self.ptr.as_ref()
     }
 
+    /// # Safety
+    ///
+    /// The pointer must point to a live, initialized `T` with no other
+    /// outstanding references.
     pub unsafe fn as_mut_ref(&mut self) -> &mut T {
         self.ptr.as_mut()
     }
 }
 
-impl<T: Sized> Clone for RawPtr<T> {
+// a well aligned placeholder, for collections that fill their slots in
+// lazily. it never points into a heap, though zero sized allocations may
+// hand back the same address.
+impl<T: Sized> RawPtr<T> {
+    pub fn dangling() -> RawPtr<T> {
+        RawPtr::from_nonnull(NonNull::dangling())
+    }
+
+    pub fn is_dangling(&self) -> bool {
+        self.ptr == NonNull::dangling()
+    }
+}
+
+// views of an alloc_array allocation as `T`s. the header's size bounds
+// `len`, and the array has to be aligned for `T`.
+impl RawPtr<u8> {
+    fn checked_view<H: AllocHeader, T>(&self, len: usize) {
+        let header_alloc_size = add_alignment_padding(size_of::<H>());
+        let header = unsafe { &*(self.as_ptr().sub(header_alloc_size) as *const H) };
+        let size = len.checked_mul(size_of::<T>());
+
+        assert!(self.as_word().is_multiple_of(align_of::<T>()), "ALLOC ERROR: array is misaligned for the slice type");
+        assert!(size.is_some_and(|size| size <= header.size() as usize), "ALLOC ERROR: slice overruns the array");
+    }
+
+    /// # Safety
+    ///
+    /// The pointer must come from `alloc_array` on a heap whose header is
+    /// `H`, and hold `len` initialized `T`s.
+    pub unsafe fn as_slice<H: AllocHeader, T>(&self, len: usize) -> &[T] {
+        self.checked_view::<H, T>(len);
+
+        from_raw_parts(self.as_ptr() as *const T, len)
+    }
+
+    /// # Safety
+    ///
+    /// As for `as_slice`, with no other outstanding references.
+    pub unsafe fn as_mut_slice<H: AllocHeader, T>(&mut self, len: usize) -> &mut [T] {
+        self.checked_view::<H, T>(len);
+
+        from_raw_parts_mut(self.as_ptr() as *mut T, len)
+    }
+}
+
+impl<T: ?Sized> Clone for RawPtr<T> {
     fn clone(&self) -> RawPtr<T> {
-        RawPtr { ptr: self.ptr }
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for RawPtr<T> {}
+
+impl<T: Sized> TryFrom<*const T> for RawPtr<T> {
+    type Error = NullPtrError;
+
+    fn try_from(ptr: *const T) -> Result<RawPtr<T>, NullPtrError> {
+        NonNull::new(ptr as *mut T).map(RawPtr::from_nonnull).ok_or(NullPtrError)
+    }
+}
+
+impl<T: Sized> From<RawPtr<T>> for NonNull<T> {
+    fn from(ptr: RawPtr<T>) -> NonNull<T> {
+        ptr.as_nonnull()
     }
 }
 
-impl<T: Sized> Copy for RawPtr<T> {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NullPtrError;
 
-impl<T: Sized> PartialEq for RawPtr<T> {
+impl<T: ?Sized> PartialEq for RawPtr<T> {
     fn eq(&self, other: &RawPtr<T>) -> bool {
-        self.ptr == other.ptr
+        core::ptr::eq(self.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+}
+
+pub struct ArrayPtr<T: Sized> {
+    ptr: RawPtr<T>,
+    len: usize,
+}
+
+impl<T: Sized> ArrayPtr<T> {
+    pub fn new(ptr: RawPtr<T>, len: usize) -> ArrayPtr<T> {
+        ArrayPtr { ptr, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_raw(&self) -> RawPtr<T> {
+        self.ptr
+    }
+
+    /// # Safety
+    ///
+    /// The pointer must point to `len` live, initialized `T`s.
+    pub unsafe fn get(&self, index: usize) -> Option<&T> {
+        if index < self.len {
+            Some(&*self.ptr.as_ptr().add(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns false, without writing, if `index` is out of bounds.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must point to `len` live, initialized `T`s with no other
+    /// outstanding references.
+    pub unsafe fn set(&mut self, index: usize, value: T) -> bool {
+        if index < self.len {
+            *(self.ptr.as_ptr() as *mut T).add(index) = value;
+
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<T: Sized> Clone for ArrayPtr<T> {
+    fn clone(&self) -> ArrayPtr<T> {
+        *self
+    }
+}
+
+impl<T: Sized> Copy for ArrayPtr<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::AllocRaw;
+    use crate::heap::tests::{SmallTestObj, TestHeader};
+    use crate::heap::ZapHeap;
+
+    #[test]
+    fn test_nonnull_round_trip() {
+        let value = 42u64;
+        let nonnull = NonNull::from(&value);
+        let ptr = RawPtr::from_nonnull(nonnull);
+
+        assert!(ptr.as_nonnull() == nonnull);
+        assert!(NonNull::from(ptr) == nonnull);
+        assert!(std::ptr::eq(ptr.as_ptr(), &value));
+        assert!(unsafe { *ptr.as_ref() } == 42);
+    }
+
+    #[test]
+    fn test_dangling() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let placeholder = RawPtr::<SmallTestObj>::dangling();
+        let copy = placeholder;
+        let ptr = heap.alloc(SmallTestObj { data: 9 }).unwrap();
+
+        assert!(placeholder.is_dangling());
+        assert!(copy == placeholder);
+        assert!(placeholder.as_word().is_multiple_of(align_of::<SmallTestObj>()));
+        assert!(!ptr.is_dangling());
+        assert!(ptr != placeholder);
+    }
+
+    #[test]
+    fn test_try_from_raw() {
+        let value = 7u32;
+        let ptr = RawPtr::try_from(&value as *const u32).unwrap();
+
+        assert!(std::ptr::eq(ptr.as_ptr(), &value));
+        assert!(RawPtr::try_from(ptr.as_ptr()).unwrap() == ptr);
+        assert!(RawPtr::<u32>::try_from(std::ptr::null()).err().unwrap() == NullPtrError);
+    }
+
+    #[test]
+    fn test_array_as_slice() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut array = heap.alloc_array((4 * size_of::<u32>()) as u32).unwrap();
+
+        unsafe {
+            let values: &mut [u32; 4] = array.as_mut_slice::<TestHeader, u32>(4).try_into().unwrap();
+
+            *values = [1, 2, 3, 0xdead_beef];
+        }
+
+        let values = unsafe { array.as_slice::<TestHeader, u32>(4) };
+
+        assert!(values == [1, 2, 3, 0xdead_beef]);
+        assert!(unsafe { array.as_slice::<TestHeader, u64>(2) }.len() == 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_slice_past_array_panics() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let array = heap.alloc_array((4 * size_of::<u32>()) as u32).unwrap();
+
+        unsafe { array.as_slice::<TestHeader, u32>(5) };
     }
 }
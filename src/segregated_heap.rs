@@ -0,0 +1,180 @@
+use alloc::sync::Arc;
+use core::cell::Cell;
+use core::hash::Hash;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use crate::allocator::{add_alignment_padding, AllocError, AllocHeader, AllocObject, AllocRaw, Trace};
+use crate::block::BlockSource;
+use crate::constants;
+use crate::heap::ZapHeap;
+use crate::raw_ptr::RawPtr;
+
+// a free slot keeps its old header so the block's object map still walks
+// it; the link to the next free slot lives in the payload, so the layout
+// can't be left to reordering
+#[repr(C)]
+struct FreeSlot<H> {
+    _header: H,
+    next: Option<NonNull<FreeSlot<H>>>,
+}
+
+pub struct SizeSegregatedHeap<H: AllocHeader> {
+    heap: ZapHeap<H>,
+    slot_size: usize,
+    free_list: Cell<Option<NonNull<FreeSlot<H>>>>,
+}
+
+impl<H: AllocHeader> SizeSegregatedHeap<H> {
+    #[cfg(any(feature = "std", test))]
+    pub fn new(object_size: usize) -> SizeSegregatedHeap<H> {
+        SizeSegregatedHeap::over(ZapHeap::new(), object_size)
+    }
+
+    pub fn with_source(object_size: usize, source: Arc<dyn BlockSource>) -> SizeSegregatedHeap<H> {
+        SizeSegregatedHeap::over(ZapHeap::with_source(source), object_size)
+    }
+
+    fn over(heap: ZapHeap<H>, object_size: usize) -> SizeSegregatedHeap<H> {
+        SizeSegregatedHeap {
+            heap,
+            slot_size: Self::slot_size(object_size),
+            free_list: Cell::new(None),
+        }
+    }
+
+    fn slot_size(object_size: usize) -> usize {
+        add_alignment_padding(add_alignment_padding(size_of::<H>()) + object_size)
+    }
+
+    pub fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+    where
+        T: AllocObject<H::TypeId>,
+    {
+        if Self::slot_size(size_of::<T>()) == self.slot_size && align_of::<T>() <= constants::ALLOC_ALIGN {
+            if let Some(slot) = self.free_list.get() {
+                unsafe {
+                    self.free_list.set(slot.as_ref().next);
+
+                    return Ok(self.heap.alloc_in_slot(slot.cast::<H>(), object));
+                }
+            }
+        }
+
+        self.heap.alloc(object)
+    }
+
+    pub fn register_trace<T>(&self)
+    where
+        T: Trace + AllocObject<H::TypeId>,
+        H::TypeId: Hash + Eq,
+    {
+        self.heap.register_trace::<T>();
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `ZapHeap::mark_from_roots`.
+    pub unsafe fn mark_from_roots(&self, roots: &[NonNull<()>])
+    where
+        H::TypeId: Hash + Eq,
+    {
+        // marking moves the line marks the free list was built from
+        self.free_list.set(None);
+        self.heap.mark_from_roots(roots);
+    }
+
+    pub fn sweep(&self) {
+        self.free_list.set(None);
+        self.heap.sweep();
+
+        if self.slot_size < size_of::<FreeSlot<H>>() {
+            return;
+        }
+
+        for slot in self.heap.dead_slots(self.slot_size) {
+            let mut slot = slot.cast::<FreeSlot<H>>();
+
+            unsafe { slot.as_mut().next = self.free_list.get() };
+            self.free_list.set(Some(slot));
+        }
+    }
+
+    pub fn free_slot_count(&self) -> usize {
+        let mut count = 0;
+        let mut slot = self.free_list.get();
+
+        while let Some(current) = slot {
+            count += 1;
+            slot = unsafe { current.as_ref().next };
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::tests::{is_marked, SmallTestObj, TestHeader};
+
+    #[test]
+    fn test_alloc_reuses_dead_slot_after_sweep() {
+        let heap = SizeSegregatedHeap::<TestHeader>::new(size_of::<SmallTestObj>());
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+        let dead = heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        let last = heap.alloc(SmallTestObj { data: 3 }).unwrap();
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped(), last.as_untyped()]) };
+        heap.sweep();
+
+        let dead_slots = heap.heap.dead_slots(heap.slot_size);
+        let hole_scans = heap.heap.stats().total_hole_scans;
+
+        assert!(heap.free_slot_count() == 1);
+        assert!(dead_slots == vec![ZapHeap::<TestHeader>::get_header(dead.as_untyped())]);
+
+        let reused = heap.alloc(SmallTestObj { data: 4 }).unwrap();
+        let reused_copy = unsafe { reused.as_ref() };
+
+        assert!(ZapHeap::<TestHeader>::get_header(reused.as_untyped()) == dead_slots[0]);
+        assert!(heap.heap.stats().total_hole_scans == hole_scans);
+        assert!(reused == dead);
+        assert!(reused_copy.data == 4);
+        assert!(!is_marked(reused.as_untyped()));
+        assert!(heap.free_slot_count() == 0);
+
+        let fresh = heap.alloc(SmallTestObj { data: 5 }).unwrap();
+
+        assert!(fresh.as_word() < last.as_word());
+    }
+
+    #[test]
+    fn test_free_list_skips_other_sizes() {
+        let heap = SizeSegregatedHeap::<TestHeader>::new(64);
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.sweep();
+
+        assert!(heap.free_slot_count() == 0);
+    }
+
+    #[test]
+    fn test_mark_drops_free_list() {
+        let heap = SizeSegregatedHeap::<TestHeader>::new(size_of::<SmallTestObj>());
+        let live = heap.alloc(SmallTestObj { data: 1 }).unwrap();
+
+        heap.alloc(SmallTestObj { data: 2 }).unwrap();
+        heap.alloc(SmallTestObj { data: 3 }).unwrap();
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+        heap.sweep();
+
+        assert!(heap.free_slot_count() == 2);
+
+        unsafe { heap.mark_from_roots(&[live.as_untyped()]) };
+
+        assert!(heap.free_slot_count() == 0);
+    }
+}
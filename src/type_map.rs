@@ -0,0 +1,31 @@
+use alloc::vec::Vec;
+
+// stands in for HashMap without std. heaps only register a handful of
+// tracers, so a linear scan is fine.
+#[derive(Clone)]
+pub(crate) struct TypeMap<K, V> {
+    entries: Vec<(K, V)>,
+}
+
+impl<K, V> TypeMap<K, V> {
+    pub fn new() -> TypeMap<K, V> {
+        TypeMap { entries: Vec::new() }
+    }
+}
+
+impl<K: Eq, V> TypeMap<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.iter().find(|(entry, _)| entry == key).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.entries.iter_mut().find(|(entry, _)| *entry == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use core::cell::Cell;
+use core::marker::PhantomData;
+use alloc::rc::Rc;
+
+use crate::raw_ptr::RawPtr;
+
+// shared between every weak ref to an object and the heap's registry, which
+// empties it when the object is swept and repoints it when it moves
+pub(crate) type WeakSlot = Rc<Cell<Option<*const ()>>>;
+
+pub struct WeakRef<T> {
+    slot: WeakSlot,
+    _object_type: PhantomData<*const T>,
+}
+
+impl<T> WeakRef<T> {
+    pub(crate) fn new(slot: WeakSlot) -> WeakRef<T> {
+        WeakRef {
+            slot,
+            _object_type: PhantomData,
+        }
+    }
+
+    pub fn upgrade(&self) -> Option<RawPtr<T>> {
+        self.slot.get().map(|ptr| RawPtr::new(ptr as *const T))
+    }
+}
+
+impl<T> Clone for WeakRef<T> {
+    fn clone(&self) -> WeakRef<T> {
+        WeakRef::new(self.slot.clone())
+    }
+}
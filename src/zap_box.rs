@@ -0,0 +1,131 @@
+use core::ops::{Deref, DerefMut};
+use core::ptr::drop_in_place;
+
+use crate::allocator::{AllocError, AllocHeader, AllocObject, AllocRaw};
+use crate::heap::ZapHeap;
+use crate::raw_ptr::RawPtr;
+
+// a `T` in the heap, borrowed for as long as the heap is. a box isn't a
+// root, so the collector doesn't know about it. dropping an owned one drops
+// `T` and deallocs the slot.
+pub struct ZapBox<'h, H: AllocHeader, T> {
+    ptr: RawPtr<T>,
+    heap: &'h ZapHeap<H>,
+    owned: bool,
+}
+
+impl<'h, H: AllocHeader, T> ZapBox<'h, H, T>
+where
+    T: AllocObject<H::TypeId>,
+{
+    /// # Safety
+    ///
+    /// The box doesn't root its object. Until the box is dropped, every mark
+    /// has to reach the object from its roots, or the heap must not be swept,
+    /// collected or reset at all.
+    pub unsafe fn new(heap: &'h ZapHeap<H>, object: T) -> Result<ZapBox<'h, H, T>, AllocError> {
+        Ok(ZapBox { ptr: heap.alloc(object)?, heap, owned: false })
+    }
+
+    /// # Safety
+    ///
+    /// Same contract as `new`. The object also mustn't be freed by anything
+    /// but dropping the box.
+    pub unsafe fn new_owned(heap: &'h ZapHeap<H>, object: T) -> Result<ZapBox<'h, H, T>, AllocError> {
+        Ok(ZapBox { ptr: heap.alloc(object)?, heap, owned: true })
+    }
+}
+
+impl<H: AllocHeader, T> ZapBox<'_, H, T> {
+    pub fn as_raw(&self) -> RawPtr<T> {
+        self.ptr
+    }
+
+    pub fn is_owned(&self) -> bool {
+        self.owned
+    }
+}
+
+impl<H: AllocHeader, T> Deref for ZapBox<'_, H, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<H: AllocHeader, T> DerefMut for ZapBox<'_, H, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut_ref() }
+    }
+}
+
+impl<H: AllocHeader, T> Drop for ZapBox<'_, H, T> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                drop_in_place(self.ptr.as_ptr() as *mut T);
+
+                let freed = self.heap.dealloc(self.ptr);
+
+                debug_assert!(freed.is_ok());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::tests::{SmallTestObj, TestHeader};
+
+    #[test]
+    fn test_mutate_through_box() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let mut boxed = unsafe { ZapBox::new(&heap, SmallTestObj { data: 1 }) }.unwrap();
+
+        boxed.data += 41;
+
+        assert!(boxed.data == 42);
+        assert!(unsafe { boxed.as_raw().as_ref() }.data == 42);
+        assert!(!boxed.is_owned());
+    }
+
+    #[test]
+    fn test_collected_box_outlives_drop() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let ptr = unsafe { ZapBox::new(&heap, SmallTestObj { data: 1 }) }.unwrap().as_raw();
+
+        assert!(heap.is_live(ptr.as_untyped()));
+        assert!(unsafe { ptr.as_ref() }.data == 1);
+    }
+
+    #[test]
+    fn test_owned_box_drop_reclaims_slot() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let boxed = unsafe { ZapBox::new_owned(&heap, SmallTestObj { data: 1 }) }.unwrap();
+        let ptr = boxed.as_raw();
+
+        drop(boxed);
+
+        assert!(!heap.is_live(ptr.as_untyped()));
+
+        let next = unsafe { ZapBox::new_owned(&heap, SmallTestObj { data: 2 }) }.unwrap();
+
+        assert!(next.as_raw() == ptr);
+        assert!(next.data == 2);
+    }
+
+    #[test]
+    fn test_owned_box_drop_after_sweep() {
+        let heap = ZapHeap::<TestHeader>::new();
+        let boxed = unsafe { ZapBox::new_owned(&heap, SmallTestObj { data: 1 }) }.unwrap();
+        let ptr = boxed.as_raw();
+
+        unsafe { heap.mark_from_roots(&[ptr.as_untyped()]) };
+        heap.sweep();
+        drop(boxed);
+
+        assert!(!heap.is_live(ptr.as_untyped()));
+    }
+}